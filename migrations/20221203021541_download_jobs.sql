@@ -0,0 +1,16 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS download_jobs
+(
+    id              BIGSERIAL PRIMARY KEY NOT NULL,
+    url             TEXT             NOT NULL,
+    requester       TEXT             NOT NULL,
+    state           TEXT             NOT NULL DEFAULT 'pending',
+    attempts        INTEGER          NOT NULL DEFAULT 0,
+    error           TEXT,
+    song            TEXT,
+    created         BIGINT           NOT NULL,
+    updated         BIGINT           NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS download_jobs_state ON download_jobs (state, id);
+CREATE INDEX IF NOT EXISTS download_jobs_requester ON download_jobs (requester);
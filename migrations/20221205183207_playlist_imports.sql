@@ -0,0 +1,14 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS playlist_imports
+(
+    id              BIGSERIAL PRIMARY KEY NOT NULL,
+    requester       TEXT             NOT NULL,
+    name            TEXT             NOT NULL,
+    url             TEXT             NOT NULL,
+    songs           TEXT[]           NOT NULL,
+    create_playlist BOOLEAN          NOT NULL DEFAULT FALSE,
+    finished        BOOLEAN          NOT NULL DEFAULT FALSE,
+    created         BIGINT           NOT NULL
+);
+
+ALTER TABLE download_jobs ADD COLUMN IF NOT EXISTS import_id BIGINT REFERENCES playlist_imports(id) ON DELETE SET NULL;
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE songs ADD COLUMN IF NOT EXISTS format TEXT NOT NULL DEFAULT 'mp3';
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE songs ADD COLUMN IF NOT EXISTS last_played BIGINT NOT NULL DEFAULT 0;
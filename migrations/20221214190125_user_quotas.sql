@@ -0,0 +1,9 @@
+-- Add migration script here
+-- overrides of the server wide defaults, null means use the default
+CREATE TABLE IF NOT EXISTS user_quotas
+(
+    id              TEXT PRIMARY KEY NOT NULL,
+    max_songs       BIGINT,
+    max_bytes       BIGINT,
+    jobs_per_hour   BIGINT
+);
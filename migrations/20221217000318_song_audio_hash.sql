@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE songs ADD COLUMN IF NOT EXISTS audio_hash TEXT;
+
+CREATE INDEX IF NOT EXISTS songs_audio_hash ON songs (audio_hash);
@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE songs ADD COLUMN IF NOT EXISTS loudness DOUBLE PRECISION;
+ALTER TABLE songs ADD COLUMN IF NOT EXISTS track_gain DOUBLE PRECISION;
+ALTER TABLE songs ADD COLUMN IF NOT EXISTS track_peak DOUBLE PRECISION;
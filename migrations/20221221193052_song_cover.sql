@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE songs ADD COLUMN IF NOT EXISTS thumbnail TEXT NOT NULL DEFAULT '';
+ALTER TABLE songs ADD COLUMN IF NOT EXISTS cover BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,5 @@
+-- Add migration script here
+ALTER TABLE songs ADD COLUMN IF NOT EXISTS raw_title TEXT NOT NULL DEFAULT '';
+ALTER TABLE songs ADD COLUMN IF NOT EXISTS raw_artist TEXT NOT NULL DEFAULT '';
+ALTER TABLE songs ADD COLUMN IF NOT EXISTS raw_uploader TEXT NOT NULL DEFAULT '';
+UPDATE songs SET raw_title = title, raw_artist = artist, raw_uploader = uploader WHERE raw_title = '';
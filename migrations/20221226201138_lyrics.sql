@@ -0,0 +1,10 @@
+-- Add migration script here
+-- plain lyrics are always set, synced holds the raw lrc when the lyrics have timestamps
+CREATE TABLE IF NOT EXISTS lyrics
+(
+    song            TEXT PRIMARY KEY NOT NULL REFERENCES songs(id) ON DELETE CASCADE,
+    plain           TEXT NOT NULL DEFAULT '',
+    synced          TEXT,
+    source          TEXT NOT NULL,
+    updated         BIGINT NOT NULL
+);
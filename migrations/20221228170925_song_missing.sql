@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE songs ADD COLUMN IF NOT EXISTS missing BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- download adds a new song, refresh and redownload work on the song in the song column
+ALTER TABLE download_jobs ADD COLUMN IF NOT EXISTS kind TEXT NOT NULL DEFAULT 'download';
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- highest songs.age_limit the user gets to see, null means the server default
+ALTER TABLE users ADD COLUMN IF NOT EXISTS max_age_limit INTEGER;
@@ -0,0 +1,54 @@
+-- Add migration script here
+-- normalized is the lowercased name with whitespace collapsed, it is what spellings are matched on
+CREATE TABLE IF NOT EXISTS artists
+(
+    id              BIGSERIAL PRIMARY KEY,
+    name            TEXT NOT NULL,
+    normalized      TEXT NOT NULL UNIQUE
+);
+
+-- other spellings of an artist that were merged into it
+CREATE TABLE IF NOT EXISTS artist_aliases
+(
+    normalized      TEXT PRIMARY KEY NOT NULL,
+    artist          BIGINT NOT NULL REFERENCES artists(id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS albums
+(
+    id              BIGSERIAL PRIMARY KEY,
+    artist          BIGINT NOT NULL REFERENCES artists(id) ON DELETE CASCADE,
+    name            TEXT NOT NULL,
+    normalized      TEXT NOT NULL,
+    UNIQUE (artist, normalized)
+);
+
+ALTER TABLE songs ADD COLUMN IF NOT EXISTS artist_id BIGINT REFERENCES artists(id) ON DELETE SET NULL;
+ALTER TABLE songs ADD COLUMN IF NOT EXISTS album_id BIGINT REFERENCES albums(id) ON DELETE SET NULL;
+ALTER TABLE songs ADD COLUMN IF NOT EXISTS track INTEGER;
+
+-- backfill from the free text columns
+INSERT INTO artists(name, normalized)
+SELECT DISTINCT ON (normalized) name, normalized FROM (
+    SELECT btrim(artist) AS name, lower(regexp_replace(btrim(artist), '\s+', ' ', 'g')) AS normalized
+    FROM songs WHERE btrim(artist) <> ''
+) s
+ORDER BY normalized, name
+ON CONFLICT DO NOTHING;
+
+UPDATE songs s SET artist_id = a.id
+FROM artists a
+WHERE a.normalized = lower(regexp_replace(btrim(s.artist), '\s+', ' ', 'g'));
+
+INSERT INTO albums(artist, name, normalized)
+SELECT DISTINCT ON (artist, normalized) artist, name, normalized FROM (
+    SELECT artist_id AS artist, btrim(album) AS name, lower(regexp_replace(btrim(album), '\s+', ' ', 'g')) AS normalized
+    FROM songs WHERE artist_id IS NOT NULL AND btrim(album) <> ''
+) s
+ORDER BY artist, normalized, name
+ON CONFLICT DO NOTHING;
+
+UPDATE songs s SET album_id = al.id
+FROM albums al
+WHERE al.artist = s.artist_id
+  AND al.normalized = lower(regexp_replace(btrim(s.album), '\s+', ' ', 'g'));
@@ -0,0 +1,5 @@
+-- Add migration script here
+-- songs cut out of a longer video by its chapters point back at the video they came from
+ALTER TABLE songs ADD COLUMN IF NOT EXISTS parent TEXT REFERENCES songs(id) ON DELETE SET NULL;
+ALTER TABLE songs ADD COLUMN IF NOT EXISTS chapter INTEGER;
+CREATE INDEX IF NOT EXISTS songs_parent ON songs(parent);
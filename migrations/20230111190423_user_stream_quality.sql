@@ -0,0 +1,4 @@
+-- Add migration script here
+-- what the stream endpoint transcodes to when the request does not say, null streams the original
+ALTER TABLE users ADD COLUMN IF NOT EXISTS stream_format TEXT;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS stream_bitrate INTEGER;
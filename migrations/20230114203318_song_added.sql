@@ -0,0 +1,3 @@
+-- Add migration script here
+-- when the song entered the library, songs that were never played are evicted by this instead
+ALTER TABLE songs ADD COLUMN IF NOT EXISTS added BIGINT NOT NULL DEFAULT extract(epoch from now())::bigint;
@@ -0,0 +1,5 @@
+-- Add migration script here
+-- null until the file was looked at, false when it has no art so it is not looked at again
+ALTER TABLE songs ALTER COLUMN cover DROP NOT NULL;
+ALTER TABLE songs ALTER COLUMN cover DROP DEFAULT;
+UPDATE songs SET cover = NULL WHERE NOT cover;
@@ -0,0 +1,5 @@
+-- Add migration script here
+-- the one place artist and album names are normalized, the backfill in artists_albums used the same
+CREATE OR REPLACE FUNCTION normalize_name(name TEXT) RETURNS TEXT
+    LANGUAGE sql IMMUTABLE STRICT
+    AS $$ SELECT lower(regexp_replace(btrim(name), '\s+', ' ', 'g')) $$;
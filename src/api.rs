@@ -1,9 +1,14 @@
+pub mod catalog;
 pub mod playlist;
 pub mod routes;
+pub mod signing;
 pub mod songs;
 mod types;
 pub mod users;
 
+use actix_files::NamedFile;
+use actix_web::{HttpRequest, HttpResponse};
+
 #[macro_export]
 macro_rules! fetch_db {
     () => {
@@ -38,3 +43,16 @@ macro_rules! time {
 pub struct BoolResult {
     admin: bool,
 }
+
+// a png or jpg that was uploaded to `dir` for `id`
+pub(crate) async fn uploaded_image(dir: &str, id: &str, req: &HttpRequest) -> HttpResponse {
+    if id.contains('/') || id.contains("..") {
+        return HttpResponse::BadRequest().finish();
+    }
+    for extension in ["png", "jpg"] {
+        if let Ok(v) = NamedFile::open_async(format!("./{dir}/{id}.{extension}")).await {
+            return v.into_response(req);
+        }
+    }
+    HttpResponse::NotFound().finish()
+}
@@ -0,0 +1,99 @@
+use crate::catalog;
+use crate::extractors::Claims;
+use crate::fetch_db;
+use crate::types::ErrorMessage;
+use crate::types::User;
+use crate::DB;
+use crate::SONG_SEARCH;
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use web::Path;
+
+#[get("/artists")]
+pub async fn artist_list(claims: Claims) -> impl Responder {
+    let mut db = fetch_db!();
+    let Some(_u) = User::from_id(&mut db, &claims.sub).await else {
+        return "[]".to_string();
+    };
+    match catalog::artists(&mut db).await {
+        Ok(v) => serde_json::to_string(&v).unwrap_or_else(|_| "[]".to_string()),
+        Err(_) => "[]".to_string(),
+    }
+}
+
+// fold the artist in the from header into the one in the into header, admin only
+#[get("/artists/merge")]
+pub async fn artist_merge(claims: Claims, req: HttpRequest) -> impl Responder {
+    let mut db = fetch_db!();
+    let Some(user) = User::from_id(&mut db, &claims.sub).await else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    if !user.admin {
+        return HttpResponse::Forbidden().finish();
+    }
+    let header = |name| {
+        req.headers()
+            .get(name)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.parse::<i64>().ok())
+    };
+    let (Some(from), Some(into)) = (header("from"), header("into")) else {
+        return HttpResponse::BadRequest().finish();
+    };
+    match catalog::merge(&mut db, from, into).await {
+        Ok(moved) => {
+            SONG_SEARCH.get().await.write().await.update(&mut db).await;
+            HttpResponse::Ok().body(moved.to_string())
+        }
+        Err(e) => HttpResponse::BadRequest().json(ErrorMessage {
+            error: Some("merge_failed".to_string()),
+            error_description: Some(e.to_string()),
+            message: "could not merge the artists".to_string(),
+        }),
+    }
+}
+
+#[get("/artists/{id}")]
+pub async fn artist_get(claims: Claims, id: Path<i64>) -> impl Responder {
+    let mut db = fetch_db!();
+    let Some(u) = User::from_id(&mut db, &claims.sub).await else {
+        return "{}".to_string();
+    };
+    let Ok(Some(mut artist)) = catalog::artist(&mut db, *id).await else {
+        return "{}".to_string();
+    };
+    artist.songs.retain(|x| x.allowed_for(&u));
+    serde_json::to_string(&artist.view(&u)).unwrap_or_else(|_| "{}".to_string())
+}
+
+// every album with its song count and length, the artist header limits it to one artist
+#[get("/albums")]
+pub async fn album_list(claims: Claims, req: HttpRequest) -> impl Responder {
+    let mut db = fetch_db!();
+    let Some(_u) = User::from_id(&mut db, &claims.sub).await else {
+        return "[]".to_string();
+    };
+    let artist = req
+        .headers()
+        .get("artist")
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.parse::<i64>().ok());
+    match catalog::albums(&mut db, artist).await {
+        Ok(v) => serde_json::to_string(&v).unwrap_or_else(|_| "[]".to_string()),
+        Err(_) => "[]".to_string(),
+    }
+}
+
+#[get("/albums/{id}")]
+pub async fn album_get(claims: Claims, id: Path<i64>) -> impl Responder {
+    let mut db = fetch_db!();
+    let Some(u) = User::from_id(&mut db, &claims.sub).await else {
+        return "{}".to_string();
+    };
+    let Ok(Some(mut album)) = catalog::album(&mut db, *id).await else {
+        return "{}".to_string();
+    };
+    // the total only counts the tracks the user can see
+    album.tracks.retain(|x| x.allowed_for(&u));
+    album.duration = album.tracks.iter().map(|x| x.duration).sum();
+    serde_json::to_string(&album.view(&u)).unwrap_or_else(|_| "{}".to_string())
+}
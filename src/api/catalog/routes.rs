@@ -0,0 +1,11 @@
+use super::handlers;
+use actix_web::{web, Scope};
+
+pub fn routes() -> Scope {
+    web::scope("/catalog")
+        .service(handlers::artist_list)
+        .service(handlers::artist_merge)
+        .service(handlers::artist_get)
+        .service(handlers::album_list)
+        .service(handlers::album_get)
+}
@@ -1,6 +1,10 @@
+use crate::api;
 use crate::extractors::Claims;
+use crate::loudness;
+use crate::signing::{self, Media, Signature};
 use crate::types::{Playlist, User};
 use crate::DB;
+use crate::SONG_SEARCH;
 use crate::{fetch_db, time};
 use actix_multipart::Multipart;
 use actix_web::{get, post, web, Error, Responder};
@@ -79,6 +83,23 @@ pub async fn delete_cover(claims: Claims) -> impl Responder {
     HttpResponse::Ok()
 }
 
+// playlist covers are stored per user id, like the upload above
+#[get("/cover/{user}")]
+pub async fn get_cover(
+    claims: Option<Claims>,
+    signature: web::Query<Signature>,
+    user: Path<String>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let Some(sub) = signing::caller(claims, &signature, Media::PlaylistCover, &user) else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    let Some(_u) = User::from_id(&mut fetch_db!(), &sub).await else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    api::uploaded_image("playlist", &user, &req).await
+}
+
 #[get("/new")]
 pub async fn playlist_new(claims: Claims, req: HttpRequest) -> impl Responder {
     let (Some(v), Some(u)) = (
@@ -118,7 +139,7 @@ pub async fn playlist_user_data(username: Path<String>, claims: Claims) -> impl
     let Ok(v) = playlist else {
         return "[]".to_string();
     };
-    let playlist: Vec<Playlist> = v
+    let mut playlist: Vec<Playlist> = v
         .into_iter()
         .filter_map(|x| {
             if x.author_id == claims.sub
@@ -132,7 +153,12 @@ pub async fn playlist_user_data(username: Path<String>, claims: Claims) -> impl
             }
         })
         .collect();
-    serde_json::to_string(&playlist).unwrap_or_default()
+    let search = SONG_SEARCH.get().await.read().await;
+    let restricted = search.restricted_for(&u);
+    playlist
+        .iter_mut()
+        .for_each(|x| x.hide_restricted(&restricted));
+    serde_json::to_string(&playlist).unwrap_or_else(|_| "[]".to_string())
 }
 
 #[get("/{username}/{playlist_name}/hash")]
@@ -187,7 +213,7 @@ pub async fn playlist_data(
     claims: Claims,
 ) -> impl Responder {
     let mut db = fetch_db!();
-    let Some(_u) = User::from_id(&mut db, &claims.sub).await else {
+    let Some(u) = User::from_id(&mut db, &claims.sub).await else {
         return String::new();
     };
     let playlist_name = playlist_name.to_string();
@@ -204,7 +230,7 @@ pub async fn playlist_data(
     let Ok(v) = playlist else {
         return String::new();
     };
-    let playlist: Vec<Playlist> = v
+    let mut playlist: Vec<Playlist> = v
         .into_iter()
         .filter_map(|x| {
             if x.author_id == claims.sub || x.edit_list.contains(&username) || x.public_playlist {
@@ -214,7 +240,48 @@ pub async fn playlist_data(
             }
         })
         .collect();
-    serde_json::to_string(&playlist).unwrap_or_default()
+    let search = SONG_SEARCH.get().await.read().await;
+    let restricted = search.restricted_for(&u);
+    playlist
+        .iter_mut()
+        .for_each(|x| x.hide_restricted(&restricted));
+    serde_json::to_string(&playlist).unwrap_or_else(|_| "[]".to_string())
+}
+
+#[get("/{username}/{playlist_name}/gain")]
+pub async fn playlist_gain(
+    username: Path<String>,
+    playlist_name: Path<String>,
+    claims: Claims,
+) -> impl Responder {
+    let mut db = fetch_db!();
+    let Some(u) = User::from_id(&mut db, &claims.sub).await else {
+        return "{}".to_string();
+    };
+    let playlist = query_as!(
+        Playlist,
+        "select * from playlist where author = $1 and name = $2",
+        username.to_string(),
+        playlist_name.to_string()
+    )
+    .fetch_optional(&mut db)
+    .await;
+    let Ok(Some(v)) = playlist else {
+        return "{}".to_string();
+    };
+    if !(v.author_id == claims.sub
+        || v.edit_list.contains(&claims.sub)
+        || v.public_playlist
+        || u.admin)
+    {
+        return "{}".to_string();
+    }
+    let search = SONG_SEARCH.get().await.read().await;
+    let songs: Vec<_> = v.songs.iter().filter_map(|x| search.get_by_id(x)).collect();
+    match loudness::group_gain(&songs) {
+        Some(v) => serde_json::to_string(&v).unwrap_or_default(),
+        None => "{}".to_string(),
+    }
 }
 
 #[get("/{username}/{playlist_name}/like")]
@@ -0,0 +1,36 @@
+use crate::extractors::Claims;
+use crate::fetch_db;
+use crate::signing::{self, Media};
+use crate::types::{Song, User};
+use crate::DB;
+use actix_web::{get, web, HttpResponse, Responder};
+use sqlx::query_as;
+use web::Path;
+
+// a short lived url for a song stream, a song cover, a profile picture or a playlist cover that
+// works in an <audio> or <img> tag, the url acts as the user that asked for it, stream urls
+// also cover the length of the song
+#[get("/{media}/{id}")]
+pub async fn sign_media(claims: Claims, path: Path<(Media, String)>) -> impl Responder {
+    let (media, id) = path.into_inner();
+    let mut db = fetch_db!();
+    let Some(u) = User::from_id(&mut db, &claims.sub).await else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    let mut playing = 0.0;
+    if matches!(media, Media::Stream | Media::Cover) {
+        let Ok(Some(song)) = query_as!(Song, "select * from songs where id = $1", id)
+            .fetch_optional(&mut db)
+            .await
+        else {
+            return HttpResponse::NotFound().finish();
+        };
+        if !song.allowed_for(&u) {
+            return HttpResponse::NotFound().finish();
+        }
+        if media == Media::Stream {
+            playing = song.duration;
+        }
+    }
+    HttpResponse::Ok().json(signing::sign(media, &id, &u.id, playing))
+}
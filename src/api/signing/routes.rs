@@ -0,0 +1,6 @@
+use super::handlers;
+use actix_web::{web, Scope};
+
+pub fn routes() -> Scope {
+    web::scope("/sign").service(handlers::sign_media)
+}
@@ -1,16 +1,46 @@
+use crate::audio;
+use crate::chapters;
+use crate::covers::{self, CoverQuery, SongView};
 use crate::extractors::Claims;
 use crate::fetch_db;
 use crate::fuzzy::SearchType;
+use crate::library::{self, LimitError};
+use crate::loudness;
+use crate::lyrics::Lyrics;
+use crate::quota::{Quota, QuotaError};
+use crate::reconcile;
+use crate::signing::{self, Media, Signature};
+use crate::transcode::{self, TranscodeQuery, Variant};
+use crate::types::DownloadJob;
+use crate::types::ErrorMessage;
+use crate::types::JobKind;
+use crate::types::PlaylistImport;
 use crate::types::Song;
+use crate::types::SongEdit;
 use crate::types::User;
 use crate::types::MAX_SEARCH_RESULTS;
+use crate::waveform::Waveform;
+use crate::youtube::Source;
+use crate::CONFIG;
 use crate::DB;
-use crate::DOWNLOAD_CACHE;
 use crate::SONG_SEARCH;
-use actix_web::{get, web, Responder};
+use actix_files::NamedFile;
+use actix_multipart::Multipart;
+use actix_web::body::SizedStream;
+use actix_web::http::header::{self, HeaderMap, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::{get, post, web, Error, Responder};
 use actix_web::{HttpRequest, HttpResponse};
+use futures::TryStreamExt;
+use log::warn;
+use sqlx::pool::PoolConnection;
 use sqlx::query_as;
+use sqlx::Postgres;
+use std::fs;
+use std::io::Write;
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncReadExt;
 use web::Path;
 
 use sqlx::query;
@@ -19,24 +49,214 @@ use sqlx::query;
 pub async fn song_new(req: HttpRequest, claims: Claims) -> impl Responder {
     let mut db = fetch_db!();
     let Some(url) = req.headers().get("url") else {
-        return HttpResponse::BadRequest();
+        return HttpResponse::BadRequest().finish();
     };
     let (Some(user), Ok(url)) = (
         User::from_id(&mut db, &claims.sub).await,
         url.to_str(),
     ) else {
-        return HttpResponse::BadRequest();
+        return HttpResponse::BadRequest().finish();
     };
-    DOWNLOAD_CACHE
-        .lock()
-        .unwrap()
-        .append(url.to_string(), user.id);
-    // let song = Song::from_url(url, &mut db, user.id).await;
-    // if let Some(_v) = song {
-    //     // TODO return JSON?
-    //     return HttpResponse::Ok();
-    // }
-    HttpResponse::Ok()
+    let source = match Source::parse(url) {
+        Ok(v) => v,
+        Err(e) => {
+            return HttpResponse::BadRequest().json(ErrorMessage {
+                error: Some("invalid_url".to_string()),
+                error_description: Some(e.to_string()),
+                message: "unsupported song url".to_string(),
+            })
+        }
+    };
+    if source.is_collection() {
+        let create_playlist = req
+            .headers()
+            .get("create_playlist")
+            .and_then(|x| x.to_str().ok())
+            .map(|x| x == "true")
+            .unwrap_or_default();
+        return match PlaylistImport::start(&mut db, &source, &user, create_playlist).await {
+            Ok(jobs) => HttpResponse::Ok().body(serde_json::to_string(&jobs).unwrap_or_default()),
+            Err(e) if e.is::<QuotaError>() => HttpResponse::TooManyRequests().json(ErrorMessage {
+                error: Some("quota_exceeded".to_string()),
+                error_description: Some(e.to_string()),
+                message: "the playlist does not fit in your quota".to_string(),
+            }),
+            Err(e) if e.is::<LimitError>() => {
+                HttpResponse::InsufficientStorage().json(ErrorMessage {
+                    error: Some("library_full".to_string()),
+                    error_description: Some(e.to_string()),
+                    message: "the library has no room for this playlist".to_string(),
+                })
+            }
+            Err(e) => HttpResponse::BadRequest().json(ErrorMessage {
+                error: Some("import_failed".to_string()),
+                error_description: Some(e.to_string()),
+                message: "could not import playlist".to_string(),
+            }),
+        };
+    }
+    // an import checks both for all of its entries at once, so these only cover a single video
+    if let Err(e) = Quota::check(&mut db, &user, 1, true).await {
+        return HttpResponse::TooManyRequests().json(ErrorMessage {
+            error: Some("quota_exceeded".to_string()),
+            error_description: Some(e.to_string()),
+            message: "you have reached your quota".to_string(),
+        });
+    }
+    if let Err(e) = library::ensure_capacity(&mut db, 1).await {
+        return HttpResponse::InsufficientStorage().json(ErrorMessage {
+            error: Some("library_full".to_string()),
+            error_description: Some(e.to_string()),
+            message: "the library has reached its limits".to_string(),
+        });
+    }
+    match DownloadJob::create(&mut db, &source.canonical_url(), &user.id, None).await {
+        Ok(job) => HttpResponse::Ok().body(job.id.to_string()),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[post("/upload")]
+pub async fn song_upload(
+    req: HttpRequest,
+    claims: Claims,
+    mut payload: Multipart,
+) -> Result<HttpResponse, Error> {
+    let Some(mime_type) = req.headers().get("ContentType") else {
+        return Ok(HttpResponse::BadRequest().into());
+    };
+    let format = match mime_type.to_str() {
+        Ok("audio/mpeg" | "audio/mp3" | "mp3") => "mp3",
+        Ok("audio/flac" | "audio/x-flac" | "flac") => "flac",
+        Ok("audio/ogg" | "audio/vorbis" | "ogg") => "ogg",
+        Ok("audio/mp4" | "audio/x-m4a" | "audio/m4a" | "m4a") => "m4a",
+        _ => return Ok(HttpResponse::BadRequest().into()),
+    };
+    let mut db = fetch_db!();
+    let Some(user) = User::from_id(&mut db, &claims.sub).await else {
+        return Ok(HttpResponse::Unauthorized().into());
+    };
+    let max_size = CONFIG.max_upload_mb as u64 * 1024 * 1024;
+    let length = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.parse::<u64>().ok());
+    if length.is_some_and(|x| x > max_size) {
+        return Ok(HttpResponse::PayloadTooLarge().into());
+    }
+    if let Err(e) = Quota::check(&mut db, &user, 1, false).await {
+        return Ok(HttpResponse::TooManyRequests().json(ErrorMessage {
+            error: Some("quota_exceeded".to_string()),
+            error_description: Some(e.to_string()),
+            message: "you have reached your quota".to_string(),
+        }));
+    }
+    if let Err(e) = library::ensure_capacity(&mut db, 1).await {
+        return Ok(HttpResponse::InsufficientStorage().json(ErrorMessage {
+            error: Some("library_full".to_string()),
+            error_description: Some(e.to_string()),
+            message: "the library has reached its limits".to_string(),
+        }));
+    }
+    if fs::create_dir_all("./songs").is_err() {
+        return Ok(HttpResponse::InternalServerError().into());
+    }
+    // write to a temporary file first since the id is the hash of the contents
+    let nonce = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let temp = TempFile(format!(
+        "./songs/{}.upload",
+        blake3::hash(format!("{}{nonce}", claims.sub).as_bytes()).to_hex()
+    ));
+    let mut hasher = blake3::Hasher::new();
+    let mut size = 0;
+    let mut init_part = false;
+    let mut filename = String::new();
+    while let Some(mut field) = payload.try_next().await? {
+        let content_disposition = field.content_disposition();
+        let Some(form_file_name) = content_disposition.get_filename() else {
+            return Ok(HttpResponse::BadRequest().into());
+        };
+        if !init_part {
+            filename = form_file_name.to_string();
+            init_part = true;
+        } else if form_file_name != filename.as_str() {
+            // if all the chunks don't have the same file name we have an issue
+            return Ok(HttpResponse::BadRequest().into());
+        }
+
+        let path = temp.0.clone();
+        // blocking op, use threadpool
+        let mut f = web::block(move || fs::OpenOptions::new().create(true).append(true).open(path))
+            .await??;
+
+        while let Some(chunk) = field.try_next().await? {
+            // chunked uploads have no content length to check up front
+            size += chunk.len() as u64;
+            if size > max_size {
+                return Ok(HttpResponse::PayloadTooLarge().into());
+            }
+            hasher.update(&chunk);
+            // blocking op, again using threadpool
+            f = web::block(move || f.write_all(&chunk).map(|_| f)).await??;
+        }
+    }
+    if !init_part {
+        return Ok(HttpResponse::BadRequest().into());
+    }
+    let id = format!("up{}", &hasher.finalize().to_hex()[..16]);
+    if SONG_SEARCH
+        .get()
+        .await
+        .read()
+        .await
+        .get_by_id(&id)
+        .is_some()
+    {
+        return Ok(HttpResponse::Conflict().body(id));
+    }
+    if fs::rename(&temp.0, format!("./songs/{id}.{format}")).is_err() {
+        return Ok(HttpResponse::InternalServerError().into());
+    }
+    let song = match Song::from_upload(id.clone(), format, &filename, &user) {
+        Ok(v) => v,
+        Err(e) => {
+            let _ = fs::remove_file(format!("./songs/{id}.{format}"));
+            return Ok(HttpResponse::BadRequest().json(ErrorMessage {
+                error: Some("invalid_audio".to_string()),
+                error_description: Some(e.to_string()),
+                message: "could not read the uploaded file".to_string(),
+            }));
+        }
+    };
+    let path = format!("songs/{id}.{format}");
+    let (audio_hash, existing) = Song::find_by_audio(&mut db, path).await;
+    if let Some(existing) = existing {
+        // already in the library under another id, link to that one instead
+        let _ = fs::remove_file(format!("./songs/{id}.{format}"));
+        return Ok(HttpResponse::Ok().body(existing.id));
+    }
+    let mut song = Song { audio_hash, ..song };
+    if song.save(&mut db).await.is_err() {
+        let _ = fs::remove_file(format!("./songs/{id}.{format}"));
+        return Ok(HttpResponse::InternalServerError().into());
+    }
+    song.analyze(&mut db).await;
+    SONG_SEARCH.get().await.write().await.update(&mut db).await;
+    Ok(HttpResponse::Ok().body(id))
+}
+
+// removes the partial upload however the request ends, after the rename there is nothing left
+// to remove
+struct TempFile(String);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
 }
 
 #[get("/clear_cache")]
@@ -48,25 +268,531 @@ pub async fn clear_cache(claims: Claims) -> impl Responder {
     if !user.admin {
         return HttpResponse::Forbidden();
     }
-    DOWNLOAD_CACHE.lock().unwrap().clear();
-    HttpResponse::Ok()
+    if DownloadJob::cancel_all(&mut db).await.is_ok() {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::InternalServerError()
+    }
+}
+
+#[get("/duplicates")]
+pub async fn song_duplicates(claims: Claims) -> impl Responder {
+    let mut db = fetch_db!();
+    let Some(user) = User::from_id(&mut db, &claims.sub).await else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    if !user.admin {
+        return HttpResponse::Forbidden().finish();
+    }
+    let search = SONG_SEARCH.get().await.read().await;
+    let duplicates: Vec<_> = library::near_duplicates(search.all())
+        .into_iter()
+        .map(|x| covers::views(x, &user))
+        .collect();
+    HttpResponse::Ok().json(duplicates)
+}
+
+// import audio files that have no row and flag rows whose file is gone, with a dry_run header
+// set to true nothing is changed and the report lists what would have been
+#[get("/reconcile")]
+pub async fn song_reconcile(claims: Claims, req: HttpRequest) -> impl Responder {
+    let mut db = fetch_db!();
+    let Some(user) = User::from_id(&mut db, &claims.sub).await else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    if !user.admin {
+        return HttpResponse::Forbidden().finish();
+    }
+    let dry_run = req
+        .headers()
+        .get("dry_run")
+        .and_then(|x| x.to_str().ok())
+        .is_some_and(|x| x == "true");
+    match reconcile::run(&mut db, &user.id, dry_run).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => HttpResponse::InternalServerError().json(ErrorMessage {
+            error: Some("reconcile_failed".to_string()),
+            error_description: Some(e.to_string()),
+            message: "could not reconcile the library".to_string(),
+        }),
+    }
+}
+
+#[get("/album_gain")]
+pub async fn album_gain(claims: Claims, req: HttpRequest) -> impl Responder {
+    let Some(album) = req.headers().get("album") else {
+        return "{}".to_string();
+    };
+    let Ok(album) = album.to_str() else {
+        return "{}".to_string();
+    };
+    let mut db = fetch_db!();
+    let Some(_u) = User::from_id(&mut db, &claims.sub).await else {
+        return "{}".to_string();
+    };
+    let search = SONG_SEARCH.get().await.read().await;
+    let songs = search.all().iter().filter(|x| x.album == album);
+    match loudness::group_gain(songs) {
+        Some(v) => serde_json::to_string(&v).unwrap_or_default(),
+        None => "{}".to_string(),
+    }
+}
+
+#[get("/jobs")]
+pub async fn job_list(claims: Claims) -> impl Responder {
+    let mut db = fetch_db!();
+    let jobs = DownloadJob::for_user(&mut db, &claims.sub).await;
+    serde_json::to_string(&jobs).unwrap_or_else(|_| "[]".to_string())
+}
+
+#[get("/jobs/{id}")]
+pub async fn job_status(claims: Claims, id: Path<i64>) -> impl Responder {
+    let mut db = fetch_db!();
+    let Some(job) = DownloadJob::from_id(&mut db, *id, &claims.sub).await else {
+        return "{}".to_string();
+    };
+    serde_json::to_string(&job).unwrap_or_else(|_| "{}".to_string())
+}
+
+#[get("/jobs/{id}/cancel")]
+pub async fn job_cancel(claims: Claims, id: Path<i64>) -> impl Responder {
+    let mut db = fetch_db!();
+    if DownloadJob::cancel(&mut db, *id, &claims.sub).await {
+        if let Some(import) = DownloadJob::from_id(&mut db, *id, &claims.sub)
+            .await
+            .and_then(|x| x.import_id)
+        {
+            let _ = PlaylistImport::finish_if_done(&mut db, import).await;
+        }
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::BadRequest()
+    }
+}
+
+#[get("/jobs/{id}/retry")]
+pub async fn job_retry(claims: Claims, id: Path<i64>) -> impl Responder {
+    let mut db = fetch_db!();
+    if DownloadJob::retry(&mut db, *id, &claims.sub).await {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::BadRequest()
+    }
 }
 
 #[get("/{song}")]
 pub async fn song_get_data(claims: Claims, song: Path<String>) -> impl Responder {
     let mut db = fetch_db!();
     let song = song.to_string();
-    let Some(_u) = User::from_id(&mut db, &claims.sub).await else {
+    let Some(u) = User::from_id(&mut db, &claims.sub).await else {
         return "".into();
     };
     let Ok(song) = query_as!(Song, "select * from songs where id = $1", song).fetch_optional(&mut db).await else {
         return "{}".into();
     };
-    if let Ok(v) = serde_json::to_string(&song) {
-        v
+    let song = song.filter(|x| x.allowed_for(&u));
+    let song = song.as_ref().map(|x| SongView::new(x, &u));
+    serde_json::to_string(&song).unwrap_or_default()
+}
+
+// edit title, artist, album and genre, the changes are sent as json in the data header
+#[get("/{song}/edit")]
+pub async fn song_edit(claims: Claims, song: Path<String>, req: HttpRequest) -> impl Responder {
+    let mut db = fetch_db!();
+    let Some(u) = User::from_id(&mut db, &claims.sub).await else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    let Some(data) = req.headers().get("data") else {
+        return HttpResponse::BadRequest().finish();
+    };
+    let Ok(edit) = serde_json::from_str::<SongEdit>(data.to_str().unwrap_or_default()) else {
+        return HttpResponse::BadRequest().finish();
+    };
+    let Ok(Some(mut song)) = query_as!(Song, "select * from songs where id = $1", song.to_string())
+        .fetch_optional(&mut db)
+        .await
+    else {
+        return HttpResponse::NotFound().finish();
+    };
+    if !song.can_edit(&u) {
+        return HttpResponse::Forbidden().finish();
+    }
+    if let Err(e) = song.edit(&mut db, edit).await {
+        return HttpResponse::BadRequest().json(ErrorMessage {
+            error: Some("edit_failed".to_string()),
+            error_description: Some(e.to_string()),
+            message: "could not edit the song".to_string(),
+        });
+    }
+    HttpResponse::Ok().json(SongView::new(&song, &u))
+}
+
+// queue a refresh or redownload of a song for its owner or an admin, responds with the job id
+async fn refetch(claims: Claims, song: Path<String>, kind: JobKind) -> HttpResponse {
+    let mut db = fetch_db!();
+    let Some(u) = User::from_id(&mut db, &claims.sub).await else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    let Ok(Some(song)) = query_as!(Song, "select * from songs where id = $1", song.to_string())
+        .fetch_optional(&mut db)
+        .await
+    else {
+        return HttpResponse::NotFound().finish();
+    };
+    if !song.can_edit(&u) {
+        return HttpResponse::Forbidden().finish();
+    }
+    if song.url.is_empty() {
+        return HttpResponse::BadRequest().json(ErrorMessage {
+            error: Some("no_source".to_string()),
+            error_description: None,
+            message: "uploaded songs can not be fetched again".to_string(),
+        });
+    }
+    match DownloadJob::create_refetch(&mut db, &song, &claims.sub, kind).await {
+        Ok(job) => HttpResponse::Ok().body(job.id.to_string()),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[get("/{song}/refresh")]
+pub async fn song_refresh(claims: Claims, song: Path<String>) -> impl Responder {
+    refetch(claims, song, JobKind::Refresh).await
+}
+
+#[get("/{song}/redownload")]
+pub async fn song_redownload(claims: Claims, song: Path<String>) -> impl Responder {
+    refetch(claims, song, JobKind::Redownload).await
+}
+
+// cut a long video into one song per chapter and put them in a playlist, admin only
+#[get("/{song}/split")]
+pub async fn song_split(claims: Claims, song: Path<String>) -> impl Responder {
+    let mut db = fetch_db!();
+    let Some(u) = User::from_id(&mut db, &claims.sub).await else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    if !u.admin {
+        return HttpResponse::Forbidden().finish();
+    }
+    let Ok(Some(song)) = query_as!(Song, "select * from songs where id = $1", song.to_string())
+        .fetch_optional(&mut db)
+        .await
+    else {
+        return HttpResponse::NotFound().finish();
+    };
+    match chapters::split(&mut db, &song, &u.id, true).await {
+        Ok(split) => HttpResponse::Ok().json(split.view(&u)),
+        Err(e @ chapters::SplitError::LibraryFull(_)) => {
+            HttpResponse::InsufficientStorage().json(ErrorMessage {
+                error: Some("library_full".to_string()),
+                error_description: Some(e.to_string()),
+                message: "the library has no room for the chapters".to_string(),
+            })
+        }
+        Err(e) => HttpResponse::BadRequest().json(ErrorMessage {
+            error: Some("split_failed".to_string()),
+            error_description: Some(e.to_string()),
+            message: "could not split the song into chapters".to_string(),
+        }),
+    }
+}
+
+// parsed lines with timestamps as json when the client accepts it, the raw lrc otherwise
+#[get("/{song}/lyrics")]
+pub async fn song_lyrics(claims: Claims, song: Path<String>, req: HttpRequest) -> impl Responder {
+    let mut db = fetch_db!();
+    let Some(_u) = User::from_id(&mut db, &claims.sub).await else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    let Ok(Some(lyrics)) = Lyrics::for_song(&mut db, &song).await else {
+        return HttpResponse::NotFound().finish();
+    };
+    let json = req
+        .headers()
+        .get("accept")
+        .and_then(|x| x.to_str().ok())
+        .is_some_and(|x| x.contains("application/json"));
+    if json {
+        HttpResponse::Ok().json(lyrics.parsed())
     } else {
-        "".into()
+        // songs without timestamps only have plain lyrics
+        HttpResponse::Ok()
+            .content_type("text/plain; charset=utf-8")
+            .body(lyrics.synced.unwrap_or(lyrics.plain))
+    }
+}
+
+// lyrics are sent as the request body, either plain text or lrc
+#[post("/{song}/lyrics")]
+pub async fn song_lyrics_upload(
+    claims: Claims,
+    song: Path<String>,
+    body: String,
+) -> impl Responder {
+    let mut db = fetch_db!();
+    let Some(u) = User::from_id(&mut db, &claims.sub).await else {
+        return HttpResponse::Unauthorized();
+    };
+    let Ok(Some(song)) = query_as!(Song, "select * from songs where id = $1", song.to_string())
+        .fetch_optional(&mut db)
+        .await
+    else {
+        return HttpResponse::NotFound();
+    };
+    if !song.can_edit(&u) {
+        return HttpResponse::Forbidden();
+    }
+    if body.trim().is_empty() {
+        return HttpResponse::BadRequest();
+    }
+    let lyrics = Lyrics::from_text(&song.id, &body, "upload");
+    if lyrics.save(&mut db).await.is_err() {
+        return HttpResponse::InternalServerError();
+    }
+    SONG_SEARCH.get().await.write().await.update(&mut db).await;
+    HttpResponse::Ok()
+}
+
+#[get("/{song}/cover")]
+pub async fn song_cover(
+    claims: Option<Claims>,
+    signature: web::Query<Signature>,
+    song: Path<String>,
+    query: web::Query<CoverQuery>,
+    req: HttpRequest,
+) -> impl Responder {
+    let Some(sub) = signing::caller(claims, &signature, Media::Cover, &song) else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    let mut db = fetch_db!();
+    let Some(_u) = User::from_id(&mut db, &sub).await else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    let Ok(Some(mut song)) = query_as!(Song, "select * from songs where id = $1", song.to_string())
+        .fetch_optional(&mut db)
+        .await
+    else {
+        return HttpResponse::NotFound().finish();
+    };
+    let path = covers::cover_path(&song.id, query.size.unwrap_or_default());
+    // songs from before covers were extracted get them on first request, songs without art
+    // are only looked at once
+    let missing = song.cover == Some(true) && !std::path::Path::new(&path).exists();
+    if song.cover.is_none() || missing {
+        if let Err(e) = song.extract_cover(&mut db).await {
+            warn!("cover extraction failed for {}: {e}", song.id);
+        }
+    }
+    if song.cover != Some(true) {
+        return HttpResponse::NotFound().finish();
+    }
+    match NamedFile::open_async(&path).await {
+        Ok(v) => v.into_response(&req),
+        Err(_) => HttpResponse::NotFound().finish(),
+    }
+}
+
+// the audio of a song for playback, NamedFile answers range requests so players can seek
+// without fetching the whole file, ?format= and ?bitrate= stream a transcoded copy and answer
+// 503 with retry-after while a copy takes too long to encode
+#[get("/{song}/stream")]
+pub async fn song_stream(
+    claims: Option<Claims>,
+    signature: web::Query<Signature>,
+    song: Path<String>,
+    query: web::Query<TranscodeQuery>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let Some(sub) = signing::caller(claims, &signature, Media::Stream, &song) else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    let mut db = fetch_db!();
+    let Some(mut u) = User::from_id(&mut db, &sub).await else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    let Ok(Some(song)) = query_as!(Song, "select * from songs where id = $1", song.to_string())
+        .fetch_optional(&mut db)
+        .await
+    else {
+        return HttpResponse::NotFound().finish();
+    };
+    // restricted songs are hidden everywhere else, so they do not exist here either
+    if !song.allowed_for(&u) || song.missing {
+        return HttpResponse::NotFound().finish();
+    }
+    let variant = match Variant::resolve(&query, &u) {
+        Ok(v) => v,
+        Err(e) => {
+            return HttpResponse::BadRequest().json(ErrorMessage {
+                error: Some("invalid_variant".to_string()),
+                error_description: Some(e.to_string()),
+                message: "can not stream in that format".to_string(),
+            })
+        }
+    };
+    let (path, mime) = match variant {
+        None => (
+            format!("songs/{}.{}", song.id, song.format),
+            audio::mime(&song.format),
+        ),
+        Some(variant) => match variant.load_or_encode(&song.id, &song.format).await {
+            Ok(Some(path)) => (path.to_string_lossy().to_string(), variant.format.mime()),
+            // a long song is still being encoded, the original is a different file so a
+            // player resuming with ranges would get bytes of the wrong one
+            Ok(None) => {
+                return HttpResponse::ServiceUnavailable()
+                    .insert_header((header::RETRY_AFTER, transcode::RETRY_AFTER_SEC))
+                    .json(ErrorMessage {
+                        error: Some("transcoding".to_string()),
+                        error_description: None,
+                        message: "the song is still being transcoded".to_string(),
+                    })
+            }
+            Err(e) => {
+                warn!("could not transcode {}: {e}", song.id);
+                return HttpResponse::InternalServerError().json(ErrorMessage {
+                    error: Some("transcode_failed".to_string()),
+                    error_description: Some(e.to_string()),
+                    message: "could not transcode the song".to_string(),
+                });
+            }
+        },
+    };
+    let Ok(file) = NamedFile::open_async(&path).await else {
+        return HttpResponse::NotFound().finish();
+    };
+    if starts_playback(&req) {
+        if let Err(e) = u.record_play(&mut db, &song.id).await {
+            warn!("could not record a play of {} by {}: {e}", song.id, u.id);
+        }
     }
+    let mut res = file.into_response(&req);
+    // NamedFile does not look at if-range, a range of a file that changed since the client
+    // fetched the rest would not fit with it so the whole file is sent instead
+    if res.status() == StatusCode::PARTIAL_CONTENT && !if_range_matches(&req, res.headers()) {
+        res = match whole_file(&path, res.headers()).await {
+            Ok(v) => v,
+            Err(_) => return HttpResponse::NotFound().finish(),
+        };
+    }
+    if res.status().is_success() {
+        res.headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static(mime));
+    }
+    res
+}
+
+// a play is the request for the start of the file, seeking asks for ranges further in
+fn starts_playback(req: &HttpRequest) -> bool {
+    match req.headers().get(header::RANGE).map(|x| x.to_str()) {
+        None => true,
+        Some(Ok(range)) => range
+            .trim()
+            .strip_prefix("bytes=")
+            .is_some_and(|x| x.starts_with("0-")),
+        Some(Err(_)) => false,
+    }
+}
+
+// an etag only matches strongly and a date only when it is exactly the last modification, as
+// rfc 9110 asks for if-range
+fn if_range_matches(req: &HttpRequest, validators: &HeaderMap) -> bool {
+    let Some(if_range) = req.headers().get(header::IF_RANGE) else {
+        return true;
+    };
+    let Ok(if_range) = if_range.to_str() else {
+        return false;
+    };
+    let if_range = if_range.trim();
+    if if_range.starts_with('"') {
+        return validators
+            .get(header::ETAG)
+            .is_some_and(|etag| etag.as_bytes() == if_range.as_bytes());
+    }
+    let Ok(date) = if_range.parse::<header::HttpDate>() else {
+        return false;
+    };
+    validators
+        .get(header::LAST_MODIFIED)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.parse::<header::HttpDate>().ok())
+        .is_some_and(|modified| modified == date)
+}
+
+// a 200 with the whole file, keeping the validators of the ranged response it replaces
+async fn whole_file(path: &str, headers: &HeaderMap) -> std::io::Result<HttpResponse> {
+    let file = tokio::fs::File::open(path).await?;
+    let len = file.metadata().await?.len();
+    let chunks = futures::stream::try_unfold(file, |mut file| async move {
+        let mut buf = Vec::with_capacity(64 * 1024);
+        match file.read_buf(&mut buf).await? {
+            0 => Ok::<_, std::io::Error>(None),
+            _ => Ok(Some((web::Bytes::from(buf), file))),
+        }
+    });
+    let mut res = HttpResponse::Ok();
+    for name in [
+        header::CONTENT_TYPE,
+        header::ETAG,
+        header::LAST_MODIFIED,
+        header::ACCEPT_RANGES,
+    ] {
+        if let Some(value) = headers.get(&name) {
+            res.insert_header((name, value.clone()));
+        }
+    }
+    Ok(res.body(SizedStream::new(len, Box::pin(chunks))))
+}
+
+// cached peaks for drawing a seek bar, json when the client accepts it and the compact binary
+// layout from waveform.rs otherwise
+#[get("/{song}/waveform")]
+pub async fn song_waveform(claims: Claims, song: Path<String>, req: HttpRequest) -> impl Responder {
+    let mut db = fetch_db!();
+    let Some(_u) = User::from_id(&mut db, &claims.sub).await else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    let Ok(Some(song)) = query_as!(Song, "select * from songs where id = $1", song.to_string())
+        .fetch_optional(&mut db)
+        .await
+    else {
+        return HttpResponse::NotFound().finish();
+    };
+    let waveform = web::block(move || Waveform::load_or_generate(&song.id, &song.format));
+    let waveform = match waveform.await {
+        Ok(Ok(v)) => v,
+        Ok(Err(e)) => {
+            warn!("could not build waveform: {e}");
+            return HttpResponse::InternalServerError().finish();
+        }
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let json = req
+        .headers()
+        .get("accept")
+        .and_then(|x| x.to_str().ok())
+        .is_some_and(|x| x.contains("application/json"));
+    if json {
+        HttpResponse::Ok().json(waveform)
+    } else {
+        HttpResponse::Ok()
+            .content_type("application/octet-stream")
+            .body(waveform.to_bytes())
+    }
+}
+
+// removes the songs with their files and every reference to them, responds with the number of
+// playlists that lost songs
+async fn delete_songs(db: &mut PoolConnection<Postgres>, ids: Vec<String>) -> HttpResponse {
+    if ids.is_empty() {
+        return HttpResponse::NotFound().finish();
+    }
+    let Ok(playlists) = Song::delete(db, &ids).await else {
+        return HttpResponse::InternalServerError().finish();
+    };
+    SONG_SEARCH.get().await.write().await.update(db).await;
+    HttpResponse::Ok().body(playlists.to_string())
 }
 
 #[get("/{song}/delete")]
@@ -74,28 +800,20 @@ pub async fn song_delete_path(claims: Claims, song: Path<String>) -> impl Respon
     let mut db = fetch_db!();
     let song = song.to_string();
     let Some(user) = User::from_id(&mut db, &claims.sub).await else {
-        return HttpResponse::Unauthorized();
+        return HttpResponse::Unauthorized().finish();
     };
-    if user.admin {
-        if query!("delete from songs where id = $1", song)
-            .execute(&mut db)
-            .await
-            .is_ok()
-        {
-            return HttpResponse::Ok();
-        }
-    } else if query!(
-        "delete from songs where added_by = $1 and id = $2",
-        claims.sub,
+    let Ok(ids) = query!(
+        "select id from songs where id = $1 and (added_by = $2 or $3)",
         song,
+        claims.sub,
+        user.admin
     )
-    .execute(&mut db)
+    .fetch_all(&mut db)
     .await
-    .is_ok()
-    {
-        return HttpResponse::Ok();
-    }
-    HttpResponse::InternalServerError()
+    else {
+        return HttpResponse::InternalServerError().finish();
+    };
+    delete_songs(&mut db, ids.into_iter().map(|x| x.id).collect()).await
 }
 
 #[get("/delete")]
@@ -106,58 +824,53 @@ pub async fn song_delete(claims: Claims, req: HttpRequest) -> impl Responder {
             User::from_id(&mut db, &claims.sub).await,
             url.to_str(),
         ) else {
-            return HttpResponse::BadRequest();
+            return HttpResponse::BadRequest().finish();
         };
-        if user.admin {
-            if query!("delete from songs where url = $1", url)
-                .execute(&mut db)
-                .await
-                .is_ok()
-            {
-                return HttpResponse::Ok();
+        // songs store the canonical form, any other link to the same video would match nothing
+        let url = match Source::parse(url) {
+            Ok(v) => v.canonical_url(),
+            Err(e) => {
+                return HttpResponse::BadRequest().json(ErrorMessage {
+                    error: Some("invalid_url".to_string()),
+                    error_description: Some(e.to_string()),
+                    message: "unsupported song url".to_string(),
+                })
             }
-        } else if query!(
-            "delete from songs where added_by = $1 and url = $2",
+        };
+        let Ok(ids) = query!(
+            "select id from songs where url = $1 and (added_by = $2 or $3)",
+            url,
             claims.sub,
-            url
+            user.admin
         )
-        .execute(&mut db)
+        .fetch_all(&mut db)
         .await
-        .is_ok()
-        {
-            return HttpResponse::Ok();
-        }
-        return HttpResponse::BadRequest();
+        else {
+            return HttpResponse::BadRequest().finish();
+        };
+        return delete_songs(&mut db, ids.into_iter().map(|x| x.id).collect()).await;
     }
     let Some(title) = req.headers().get("title") else {
-        return HttpResponse::BadRequest();
+        return HttpResponse::BadRequest().finish();
     };
     let (Some(user), Ok(title)) = (
         User::from_id(&mut db, &claims.sub).await,
         title.to_str(),
     ) else {
-        return HttpResponse::Unauthorized();
+        return HttpResponse::Unauthorized().finish();
     };
-    if user.admin {
-        if query!("delete from songs where title = $1", title)
-            .execute(&mut db)
-            .await
-            .is_ok()
-        {
-            return HttpResponse::Ok();
-        }
-    } else if query!(
-        "delete from songs where added_by = $1 and title = $2",
+    let Ok(ids) = query!(
+        "select id from songs where title = $1 and (added_by = $2 or $3)",
+        title,
         claims.sub,
-        title
+        user.admin
     )
-    .execute(&mut db)
+    .fetch_all(&mut db)
     .await
-    .is_ok()
-    {
-        return HttpResponse::Ok();
-    }
-    HttpResponse::BadRequest()
+    else {
+        return HttpResponse::BadRequest().finish();
+    };
+    delete_songs(&mut db, ids.into_iter().map(|x| x.id).collect()).await
 }
 
 #[get("/{song}/like")]
@@ -204,6 +917,7 @@ pub async fn song_search(claims: Claims, req: HttpRequest) -> impl Responder {
             "title" => SearchType::Title,
             "user" => SearchType::User,
             "id" => SearchType::Id,
+            "lyrics" => SearchType::Lyrics,
             _ => SearchType::Default,
         }
     } else {
@@ -220,7 +934,7 @@ pub async fn song_search(claims: Claims, req: HttpRequest) -> impl Responder {
     };
     let mut db = fetch_db!();
     if let Some(search_term) = req.headers().get("search") {
-        let (Some(_user), Ok(search_term)) = (
+        let (Some(user), Ok(search_term)) = (
             User::from_id(&mut db, &claims.sub).await,
             search_term.to_str(),
         ) else {
@@ -231,21 +945,21 @@ pub async fn song_search(claims: Claims, req: HttpRequest) -> impl Responder {
             let Ok(song) = query_as!(Song, "select * from songs where id = $1", search_term).fetch_optional(&mut db).await else {
                 return "{}".into();
             };
-            return if let Ok(v) = serde_json::to_string(&song) {
-                v
-            } else {
-                "".into()
-            };
+            let song = song.filter(|x| x.allowed_for(&user));
+            let song = song.as_ref().map(|x| SongView::new(x, &user));
+            return serde_json::to_string(&song).unwrap_or_default();
         }
         let search_term = Arc::new(search_term.to_string());
+        let max_age = user.age_limit();
         let res = tokio::spawn(async move {
             let search_term = search_term.clone();
-            serde_json::to_string(&SONG_SEARCH.get().await.write().await.search(
-                &search_term,
-                search_type,
-                search_count,
-            ))
-            .ok()
+            let search = SONG_SEARCH.get().await.read().await;
+            let songs = search.search(&search_term, search_type, search_count, max_age);
+            let songs: Vec<_> = songs
+                .into_iter()
+                .map(|(song, score)| (SongView::new(song, &user), score))
+                .collect();
+            serde_json::to_string(&songs).ok()
         })
         .await;
         return if let Ok(Some(v)) = res {
@@ -256,3 +970,72 @@ pub async fn song_search(claims: Claims, req: HttpRequest) -> impl Responder {
     }
     String::from("[]")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn validators() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ETAG, HeaderValue::from_static("\"1f:2a:5f:0\""));
+        headers.insert(
+            header::LAST_MODIFIED,
+            HeaderValue::from_static("Sun, 15 Jan 2023 10:00:00 GMT"),
+        );
+        headers
+    }
+
+    fn matches(if_range: Option<&str>) -> bool {
+        let mut req = TestRequest::get().insert_header((header::RANGE, "bytes=100-"));
+        if let Some(if_range) = if_range {
+            req = req.insert_header((header::IF_RANGE, if_range));
+        }
+        if_range_matches(&req.to_http_request(), &validators())
+    }
+
+    #[test]
+    fn if_range() {
+        assert!(matches(None));
+        assert!(matches(Some("\"1f:2a:5f:0\"")));
+        assert!(matches(Some("Sun, 15 Jan 2023 10:00:00 GMT")));
+        assert!(!matches(Some("\"1f:2a:60:0\"")));
+        assert!(!matches(Some("W/\"1f:2a:5f:0\"")));
+        assert!(!matches(Some("Sun, 15 Jan 2023 10:00:01 GMT")));
+        assert!(!matches(Some("Sat, 14 Jan 2023 10:00:00 GMT")));
+        assert!(!matches(Some("yesterday")));
+    }
+
+    #[test]
+    fn playback_start() {
+        let start = |range: Option<&str>| {
+            let mut req = TestRequest::get();
+            if let Some(range) = range {
+                req = req.insert_header((header::RANGE, range));
+            }
+            starts_playback(&req.to_http_request())
+        };
+        assert!(start(None));
+        assert!(start(Some("bytes=0-")));
+        assert!(start(Some("bytes=0-1023")));
+        assert!(!start(Some("bytes=4096-")));
+    }
+
+    #[actix_web::test]
+    async fn whole_file_keeps_validators() {
+        let path = std::env::temp_dir().join("seanify-whole-file-test");
+        fs::write(&path, vec![7u8; 200_000]).unwrap();
+        let mut headers = validators();
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("audio/mpeg"));
+        let res = whole_file(path.to_str().unwrap(), &headers).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(header::ETAG), headers.get(header::ETAG));
+        assert_eq!(
+            res.headers().get(header::CONTENT_TYPE),
+            headers.get(header::CONTENT_TYPE)
+        );
+        let body = actix_web::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(body.len(), 200_000);
+        fs::remove_file(&path).unwrap();
+    }
+}
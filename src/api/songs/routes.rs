@@ -4,8 +4,25 @@ use actix_web::{web, Scope};
 pub fn routes() -> Scope {
     web::scope("/songs")
         .service(handlers::song_new)
+        .service(handlers::song_upload)
         .service(handlers::clear_cache)
+        .service(handlers::song_duplicates)
+        .service(handlers::song_reconcile)
+        .service(handlers::album_gain)
+        .service(handlers::job_list)
+        .service(handlers::job_status)
+        .service(handlers::job_cancel)
+        .service(handlers::job_retry)
         .service(handlers::song_get_data)
+        .service(handlers::song_stream)
+        .service(handlers::song_waveform)
+        .service(handlers::song_cover)
+        .service(handlers::song_edit)
+        .service(handlers::song_lyrics)
+        .service(handlers::song_lyrics_upload)
+        .service(handlers::song_refresh)
+        .service(handlers::song_redownload)
+        .service(handlers::song_split)
         .service(handlers::song_delete_path)
         .service(handlers::song_delete)
         .service(handlers::song_like)
@@ -1,10 +1,14 @@
+use crate::api;
 use crate::api::types::{Message, Metadata};
 use crate::api::BoolResult;
 use crate::extractors::Claims;
+use crate::quota::Quota;
+use crate::signing::{self, Media, Signature};
+use crate::transcode;
 use crate::types::User;
-use crate::DB;
 use crate::{fetch_db, response};
 use crate::{time, BRANCH, VERSION};
+use crate::{CONFIG, DB};
 use actix_multipart::Multipart;
 use actix_web::{get, post, web, Error, Responder};
 use actix_web::{HttpRequest, HttpResponse};
@@ -330,26 +334,31 @@ pub async fn listen(claims: Claims, song: web::Path<String>) -> impl Responder {
         return HttpResponse::BadRequest();
     }
     let mut db = fetch_db!();
-    let result = query_as!(User, "select * from users where id = $1", claims.sub)
-        .fetch_optional(&mut db)
-        .await;
-    if let Ok(Some(mut v)) = result {
-        v.now_playing(song.to_string());
-        return if query!(
-            "update users set last_played = $1 where id = $2",
-            &v.last_played,
-            claims.sub
-        )
-        .fetch_optional(&mut db)
-        .await
-        .is_ok()
-        {
-            HttpResponse::Ok()
-        } else {
-            HttpResponse::BadRequest()
-        };
+    let Some(mut u) = User::from_id(&mut db, &claims.sub).await else {
+        return HttpResponse::BadRequest();
+    };
+    if u.record_play(&mut db, &song).await.is_ok() {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::BadRequest()
     }
-    HttpResponse::BadRequest()
+}
+
+// profile pictures are only served to signed in users or through a signed url
+#[get("/pfp/{user}")]
+pub async fn get_pfp(
+    claims: Option<Claims>,
+    signature: web::Query<Signature>,
+    user: web::Path<String>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let Some(sub) = signing::caller(claims, &signature, Media::Pfp, &user) else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    let Some(_u) = User::from_id(&mut fetch_db!(), &sub).await else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    api::uploaded_image("profiles", &user, &req).await
 }
 
 #[get("/get/id/{user}")]
@@ -385,3 +394,130 @@ pub async fn get_user_from_name(claims: Claims, user: web::Path<String>) -> impl
     }
     "".to_string()
 }
+
+#[get("/quota")]
+pub async fn get_quota(claims: Claims) -> impl Responder {
+    let mut db = fetch_db!();
+    let Some(user) = User::from_id(&mut db, &claims.sub).await else {
+        return "{}".to_string();
+    };
+    match Quota::usage(&mut db, &user).await {
+        Ok(v) => serde_json::to_string(&v).unwrap_or_default(),
+        Err(_) => "{}".to_string(),
+    }
+}
+
+#[get("/quota/{user}")]
+pub async fn set_quota(
+    claims: Claims,
+    user: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let mut db = fetch_db!();
+    let Some(admin) = User::from_id(&mut db, &claims.sub).await else {
+        return HttpResponse::Unauthorized();
+    };
+    if !admin.admin {
+        return HttpResponse::Forbidden();
+    }
+    let Some(data) = req.headers().get("data") else {
+        return HttpResponse::BadRequest();
+    };
+    let Ok(quota) = serde_json::from_str::<Quota>(data.to_str().unwrap_or_default()) else {
+        return HttpResponse::BadRequest();
+    };
+    if User::from_id(&mut db, &user).await.is_none() {
+        return HttpResponse::NotFound();
+    }
+    if quota.set(&mut db, &user).await.is_ok() {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::InternalServerError()
+    }
+}
+
+// set the highest age limit of songs a user gets to see from the age_limit header, "default"
+// goes back to the server default, users can lower their own limit and admins can set anyone's
+#[get("/age_limit/{user}")]
+pub async fn set_age_limit(
+    claims: Claims,
+    user: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let mut db = fetch_db!();
+    let Some(caller) = User::from_id(&mut db, &claims.sub).await else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    let Some(target) = User::from_id(&mut db, &user).await else {
+        return HttpResponse::NotFound().finish();
+    };
+    let Some(limit) = req.headers().get("age_limit").and_then(|x| x.to_str().ok()) else {
+        return HttpResponse::BadRequest().finish();
+    };
+    let limit = match limit {
+        "default" => None,
+        v => match v.parse::<i32>() {
+            Ok(v) if v >= 0 => Some(v),
+            _ => return HttpResponse::BadRequest().finish(),
+        },
+    };
+    let effective = limit.unwrap_or(CONFIG.default_max_age_limit);
+    let allowed = caller.admin || (caller.id == target.id && effective <= target.age_limit());
+    if !allowed {
+        return HttpResponse::Forbidden().finish();
+    }
+    if query!(
+        "update users set max_age_limit = $1 where id = $2",
+        limit,
+        target.id
+    )
+    .execute(&mut db)
+    .await
+    .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok().body(effective.to_string())
+}
+
+// the format and bitrate streams default to, "default" in the format header streams the
+// original file again
+#[get("/stream_quality")]
+pub async fn set_stream_quality(claims: Claims, req: HttpRequest) -> impl Responder {
+    let mut db = fetch_db!();
+    let Some(u) = User::from_id(&mut db, &claims.sub).await else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    let header = |name| req.headers().get(name).and_then(|x| x.to_str().ok());
+    let Some(format) = header("format") else {
+        return HttpResponse::BadRequest().finish();
+    };
+    let format = match format {
+        "default" => None,
+        v => match transcode::Format::parse(v) {
+            Ok(_) => Some(v.to_string()),
+            Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+        },
+    };
+    let bitrate = match header("bitrate").map(|x| x.parse::<u32>()) {
+        None => None,
+        Some(Ok(v)) => match transcode::check_bitrate(v) {
+            Ok(v) => Some(v as i32),
+            Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+        },
+        Some(Err(_)) => return HttpResponse::BadRequest().finish(),
+    };
+    if query!(
+        "update users set stream_format = $1, stream_bitrate = $2 where id = $3",
+        format,
+        bitrate,
+        u.id
+    )
+    .execute(&mut db)
+    .await
+    .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok().finish()
+}
@@ -5,6 +5,7 @@ pub fn routes() -> Scope {
     web::scope("/users")
         .service(handlers::upload_pfp)
         .service(handlers::delete_pfp)
+        .service(handlers::get_pfp)
         .service(handlers::user_taken)
         .service(handlers::user_new)
         .service(handlers::user_self)
@@ -16,4 +17,8 @@ pub fn routes() -> Scope {
         .service(handlers::listen)
         .service(handlers::get_user_from_id)
         .service(handlers::get_user_from_name)
+        .service(handlers::get_quota)
+        .service(handlers::set_quota)
+        .service(handlers::set_age_limit)
+        .service(handlers::set_stream_quality)
 }
@@ -0,0 +1,199 @@
+use anyhow::{anyhow, Result};
+use id3::{Tag, TagLike, Version};
+use std::{fs::File, path::Path};
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::DecoderOptions,
+    errors::Error as SymphoniaError,
+    formats::{FormatOptions, FormatReader},
+    io::MediaSourceStream,
+    meta::{MetadataOptions, MetadataRevision, StandardTagKey, StandardVisualKey, Visual},
+    probe::{Hint, ProbeResult},
+};
+
+#[derive(Default)]
+pub struct AudioTags {
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub genre: String,
+    // seconds
+    pub duration: f64,
+}
+
+impl AudioTags {
+    fn apply(&mut self, revision: &MetadataRevision) {
+        for tag in revision.tags() {
+            let field = match tag.std_key {
+                Some(StandardTagKey::TrackTitle) => &mut self.title,
+                Some(StandardTagKey::Artist) => &mut self.artist,
+                Some(StandardTagKey::Album) => &mut self.album,
+                Some(StandardTagKey::Genre) => &mut self.genre,
+                _ => continue,
+            };
+            *field = tag.value.to_string();
+        }
+    }
+}
+
+fn probe(path: &Path) -> Result<ProbeResult> {
+    let file = File::open(path)?;
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|x| x.to_str()) {
+        hint.with_extension(extension);
+    }
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+    Ok(symphonia::default::get_probe().format(
+        &hint,
+        stream,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?)
+}
+
+// read tags and duration out of any of the supported formats
+pub fn read_tags(path: impl AsRef<Path>) -> Result<AudioTags> {
+    let path = path.as_ref();
+    let mut probed = probe(path)?;
+    let mut tags = AudioTags::default();
+    // tags can be in front of the container (id3) or inside of it
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|x| x.current()) {
+        tags.apply(revision);
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        tags.apply(revision);
+    }
+    let track = probed
+        .format
+        .default_track()
+        .ok_or_else(|| anyhow!("no audio track in {}", path.display()))?;
+    if let (Some(time_base), Some(frames)) =
+        (track.codec_params.time_base, track.codec_params.n_frames)
+    {
+        let time = time_base.calc_time(frames);
+        tags.duration = time.seconds as f64 + time.frac;
+    }
+    Ok(tags)
+}
+
+// content type to serve a file in songs/ with
+pub fn mime(format: &str) -> &'static str {
+    match format {
+        "mp3" => "audio/mpeg",
+        "flac" => "audio/flac",
+        "ogg" => "audio/ogg",
+        "m4a" => "audio/mp4",
+        _ => "application/octet-stream",
+    }
+}
+
+// track number from the tags, id3 stores it as "3" or "3/12"
+pub fn track_number(path: impl AsRef<Path>) -> Result<Option<i32>> {
+    let mut probed = probe(path.as_ref())?;
+    let mut track = None;
+    let mut find = |revision: &MetadataRevision| {
+        for tag in revision.tags() {
+            if tag.std_key == Some(StandardTagKey::TrackNumber) {
+                let value = tag.value.to_string();
+                let number = value.split('/').next().unwrap_or_default().trim();
+                track = number.parse::<i32>().ok().or(track);
+            }
+        }
+    };
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|x| x.current()) {
+        find(revision);
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        find(revision);
+    }
+    Ok(track)
+}
+
+// decode the whole file and hand every block of interleaved samples to `sink` along with the
+// channel count and sample rate, this is blocking so run it on the threadpool
+pub fn decode(path: impl AsRef<Path>, mut sink: impl FnMut(&[f32], usize, u32)) -> Result<()> {
+    let path = path.as_ref();
+    let mut format: Box<dyn FormatReader> = probe(path)?.format;
+    let track = format
+        .default_track()
+        .ok_or_else(|| anyhow!("no audio track in {}", path.display()))?;
+    let track_id = track.id;
+    let mut decoder =
+        symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(v) => v,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                break
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(v) => v,
+            // a corrupt frame is not worth failing the whole file over
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let spec = *decoded.spec();
+        let buffer =
+            buffer.get_or_insert_with(|| SampleBuffer::<f32>::new(decoded.capacity() as u64, spec));
+        if buffer.capacity() < decoded.capacity() * spec.channels.count() {
+            *buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        }
+        buffer.copy_interleaved_ref(decoded);
+        sink(buffer.samples(), spec.channels.count(), spec.rate);
+    }
+    Ok(())
+}
+
+// hash of the decoded samples, the same recording with different tags or containers hashes
+// the same as long as it decodes to the same audio
+pub fn content_hash(path: impl AsRef<Path>) -> Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    decode(path, |samples, _, _| {
+        let bytes: Vec<u8> = samples.iter().flat_map(|x| x.to_le_bytes()).collect();
+        hasher.update(&bytes);
+    })?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+// the embedded cover art of a file, the front cover if it is tagged as one and otherwise the
+// first picture in the file
+pub fn cover_art(path: impl AsRef<Path>) -> Result<Option<Vec<u8>>> {
+    let mut probed = probe(path.as_ref())?;
+    let mut visuals: Vec<Visual> = vec![];
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|x| x.current()) {
+        visuals.extend_from_slice(revision.visuals());
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        visuals.extend_from_slice(revision.visuals());
+    }
+    let front = visuals
+        .iter()
+        .position(|x| x.usage == Some(StandardVisualKey::FrontCover))
+        .unwrap_or_default();
+    Ok(visuals.into_iter().nth(front).map(|x| x.data.into_vec()))
+}
+
+// write the edited fields back into the id3 tag of an mp3, other tags in the file are kept
+pub fn write_id3(path: impl AsRef<Path>, tags: &AudioTags) -> Result<()> {
+    let path = path.as_ref();
+    let mut tag = match Tag::read_from_path(path) {
+        Ok(v) => v,
+        Err(id3::Error {
+            kind: id3::ErrorKind::NoTag,
+            ..
+        }) => Tag::new(),
+        Err(e) => return Err(e.into()),
+    };
+    tag.set_title(&tags.title);
+    tag.set_artist(&tags.artist);
+    tag.set_album(&tags.album);
+    tag.set_genre(&tags.genre);
+    tag.write_to_path(path, Version::Id3v24)?;
+    Ok(())
+}
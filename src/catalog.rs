@@ -0,0 +1,324 @@
+use crate::{
+    audio,
+    covers::{self, SongView},
+    types::{Song, User},
+};
+use actix_web::web;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use sqlx::{pool::PoolConnection, query, query_as, Connection, Postgres};
+
+#[derive(Serialize)]
+pub struct ArtistSummary {
+    pub id: i64,
+    pub name: String,
+    pub songs: i64,
+    pub albums: i64,
+}
+
+#[derive(Serialize)]
+pub struct AlbumSummary {
+    pub id: i64,
+    pub name: String,
+    pub artist_id: i64,
+    pub artist: String,
+    pub songs: i64,
+    pub duration: f64,
+}
+
+#[derive(Serialize)]
+pub struct ArtistDetail {
+    pub id: i64,
+    pub name: String,
+    // other spellings that were merged into this artist
+    pub aliases: Vec<String>,
+    pub albums: Vec<AlbumSummary>,
+    #[serde(skip_serializing)]
+    pub songs: Vec<Song>,
+}
+
+// an artist page for one user, the songs go out through SongView
+#[derive(Serialize)]
+pub struct ArtistView<'a> {
+    #[serde(flatten)]
+    artist: &'a ArtistDetail,
+    songs: Vec<SongView<&'a Song>>,
+}
+
+impl ArtistDetail {
+    pub fn view(&self, user: &User) -> ArtistView<'_> {
+        ArtistView {
+            artist: self,
+            songs: covers::views(&self.songs, user),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct AlbumDetail {
+    pub id: i64,
+    pub name: String,
+    pub artist_id: i64,
+    pub artist: String,
+    pub duration: f64,
+    // in track order, songs without a track number come last
+    #[serde(skip_serializing)]
+    pub tracks: Vec<Song>,
+}
+
+// an album page for one user, the tracks go out through SongView
+#[derive(Serialize)]
+pub struct AlbumView<'a> {
+    #[serde(flatten)]
+    album: &'a AlbumDetail,
+    tracks: Vec<SongView<&'a Song>>,
+}
+
+impl AlbumDetail {
+    pub fn view(&self, user: &User) -> AlbumView<'_> {
+        AlbumView {
+            album: self,
+            tracks: covers::views(&self.tracks, user),
+        }
+    }
+}
+
+// point the song at the artist and album rows for its artist and album text, creating them if
+// this is the first song with that spelling, merged spellings resolve through the aliases,
+// names are compared by the normalize_name sql function so they match what the backfill did
+pub async fn link(db: &mut PoolConnection<Postgres>, song: &mut Song) -> Result<()> {
+    let artist = song.artist.trim();
+    let album = song.album.trim();
+    song.artist_id = None;
+    song.album_id = None;
+    if !artist.is_empty() {
+        let alias = query!(
+            "select artist from artist_aliases where normalized = normalize_name($1)",
+            song.artist
+        )
+        .fetch_optional(&mut *db)
+        .await?;
+        let artist_id = match alias {
+            Some(alias) => alias.artist,
+            // the no-op update makes returning work when the artist already exists
+            None => {
+                query!(
+                    r#"insert into artists(name, normalized) values($1, normalize_name($2))
+                    on conflict (normalized) do update set normalized = excluded.normalized
+                    returning id"#,
+                    artist,
+                    song.artist
+                )
+                .fetch_one(&mut *db)
+                .await?
+                .id
+            }
+        };
+        song.artist_id = Some(artist_id);
+        if !album.is_empty() {
+            let album_id = query!(
+                r#"insert into albums(artist, name, normalized) values($1, $2, normalize_name($3))
+                on conflict (artist, normalized) do update set normalized = excluded.normalized
+                returning id"#,
+                artist_id,
+                album,
+                song.album
+            )
+            .fetch_one(&mut *db)
+            .await?
+            .id;
+            song.album_id = Some(album_id);
+        }
+    }
+    query!(
+        "update songs set artist_id = $1, album_id = $2 where id = $3",
+        song.artist_id,
+        song.album_id,
+        song.id
+    )
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+pub async fn read_track(db: &mut PoolConnection<Postgres>, song: &mut Song) -> Result<()> {
+    let path = format!("songs/{}.{}", song.id, song.format);
+    song.track = web::block(move || audio::track_number(path)).await??;
+    query!(
+        "update songs set track = $1 where id = $2",
+        song.track,
+        song.id
+    )
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+// artists that have at least one song
+pub async fn artists(db: &mut PoolConnection<Postgres>) -> Result<Vec<ArtistSummary>> {
+    Ok(query_as!(
+        ArtistSummary,
+        r#"select a.id, a.name, count(s.id) as "songs!", count(distinct s.album_id) as "albums!"
+        from artists a join songs s on s.artist_id = a.id
+        group by a.id order by lower(a.name)"#
+    )
+    .fetch_all(db)
+    .await?)
+}
+
+// albums that have at least one song, optionally only those of one artist
+pub async fn albums(
+    db: &mut PoolConnection<Postgres>,
+    artist: Option<i64>,
+) -> Result<Vec<AlbumSummary>> {
+    Ok(query_as!(
+        AlbumSummary,
+        r#"select al.id, al.name, al.artist as artist_id, a.name as artist,
+            count(s.id) as "songs!", sum(s.duration) as "duration!"
+        from albums al
+        join artists a on a.id = al.artist
+        join songs s on s.album_id = al.id
+        where $1::bigint is null or al.artist = $1
+        group by al.id, a.name order by lower(a.name), lower(al.name)"#,
+        artist
+    )
+    .fetch_all(db)
+    .await?)
+}
+
+pub async fn artist(db: &mut PoolConnection<Postgres>, id: i64) -> Result<Option<ArtistDetail>> {
+    let Some(artist) = query!("select id, name from artists where id = $1", id)
+        .fetch_optional(&mut *db)
+        .await?
+    else {
+        return Ok(None);
+    };
+    let aliases = query!(
+        "select normalized from artist_aliases where artist = $1 order by normalized",
+        id
+    )
+    .fetch_all(&mut *db)
+    .await?
+    .into_iter()
+    .map(|x| x.normalized)
+    .collect();
+    let albums = albums(db, Some(id)).await?;
+    let songs = query_as!(
+        Song,
+        "select * from songs where artist_id = $1 order by upload_date desc, title",
+        id
+    )
+    .fetch_all(&mut *db)
+    .await?;
+    Ok(Some(ArtistDetail {
+        id: artist.id,
+        name: artist.name,
+        aliases,
+        albums,
+        songs,
+    }))
+}
+
+pub async fn album(db: &mut PoolConnection<Postgres>, id: i64) -> Result<Option<AlbumDetail>> {
+    let Some(album) = query!(
+        r#"select al.id, al.name, al.artist, a.name as artist_name
+        from albums al join artists a on a.id = al.artist where al.id = $1"#,
+        id
+    )
+    .fetch_optional(&mut *db)
+    .await?
+    else {
+        return Ok(None);
+    };
+    let tracks = query_as!(
+        Song,
+        "select * from songs where album_id = $1 order by track nulls last, upload_date, title",
+        id
+    )
+    .fetch_all(&mut *db)
+    .await?;
+    Ok(Some(AlbumDetail {
+        id: album.id,
+        name: album.name,
+        artist_id: album.artist,
+        artist: album.artist_name,
+        duration: tracks.iter().map(|x| x.duration).sum(),
+        tracks,
+    }))
+}
+
+// fold the artist `from` into `into`, its songs take the name of `into`, albums with the same
+// name are combined and the spellings of `from` become aliases so new songs land on `into`,
+// returns how many songs were moved
+pub async fn merge(db: &mut PoolConnection<Postgres>, from: i64, into: i64) -> Result<u64> {
+    if from == into {
+        return Err(anyhow!("can not merge an artist into itself"));
+    }
+    let mut tx = db.begin().await?;
+    let exists = query!(
+        "select count(*) as \"count!\" from artists where id = $1 or id = $2",
+        from,
+        into
+    )
+    .fetch_one(&mut tx)
+    .await?
+    .count;
+    if exists != 2 {
+        return Err(anyhow!("no such artist"));
+    }
+    let moved = query!(
+        r#"update songs s set artist_id = a.id, artist = a.name,
+            default_search = s.title || ' ' || a.name || ' ' || s.album
+        from artists a where a.id = $2 and s.artist_id = $1"#,
+        from,
+        into
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+    query!(
+        r#"update songs s set album_id = t.id
+        from albums f join albums t on t.normalized = f.normalized and t.artist = $2
+        where f.artist = $1 and s.album_id = f.id"#,
+        from,
+        into
+    )
+    .execute(&mut tx)
+    .await?;
+    query!(
+        r#"delete from albums f using albums t
+        where f.artist = $1 and t.artist = $2 and t.normalized = f.normalized"#,
+        from,
+        into
+    )
+    .execute(&mut tx)
+    .await?;
+    query!(
+        "update albums set artist = $2 where artist = $1",
+        from,
+        into
+    )
+    .execute(&mut tx)
+    .await?;
+    query!(
+        "update artist_aliases set artist = $2 where artist = $1",
+        from,
+        into
+    )
+    .execute(&mut tx)
+    .await?;
+    query!(
+        r#"insert into artist_aliases(normalized, artist)
+        select normalized, $2 from artists where id = $1
+        on conflict (normalized) do update set artist = excluded.artist"#,
+        from,
+        into
+    )
+    .execute(&mut tx)
+    .await?;
+    query!("delete from artists where id = $1", from)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+    Ok(moved)
+}
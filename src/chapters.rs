@@ -0,0 +1,325 @@
+use crate::{
+    audio::{self, AudioTags},
+    covers::{self, SongView},
+    library::{self, LimitError},
+    quota::{Quota, QuotaError},
+    time, titles,
+    types::{unix_time, Playlist, Song, User},
+    youtube::{Chapter, VideoData},
+    SONG_SEARCH,
+};
+use actix_web::web;
+use derive_more::Display;
+use log::{info, warn};
+use serde::Serialize;
+use sqlx::{pool::PoolConnection, query, Postgres};
+use std::{
+    fs,
+    io::ErrorKind,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::process::Command;
+
+const FFMPEG: &str = "ffmpeg";
+// chapters shorter than this are intros or markers rather than tracks
+const MIN_CHAPTER_SECONDS: f64 = 1.0;
+
+#[derive(Debug, Display)]
+pub enum SplitError {
+    #[display(fmt = "{} has no chapters", _0)]
+    NoChapters(String),
+    #[display(fmt = "{} was already split into chapters", _0)]
+    AlreadySplit(String),
+    #[display(fmt = "{} is itself a chapter of another song", _0)]
+    IsChapter(String),
+    #[display(fmt = "{} is not installed", FFMPEG)]
+    NotInstalled,
+    #[display(fmt = "ffmpeg exited with {}", _0)]
+    Failed(String),
+    #[display(fmt = "{}", _0)]
+    Quota(QuotaError),
+    #[display(fmt = "{}", _0)]
+    LibraryFull(LimitError),
+    #[display(fmt = "{}", _0)]
+    Other(anyhow::Error),
+}
+
+impl std::error::Error for SplitError {}
+
+impl From<anyhow::Error> for SplitError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::Other(e)
+    }
+}
+
+impl From<sqlx::Error> for SplitError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Other(e.into())
+    }
+}
+
+#[derive(Serialize)]
+pub struct Split {
+    #[serde(skip_serializing)]
+    pub songs: Vec<Song>,
+    // name of the playlist with the chapters in order, if one was made
+    pub playlist: Option<String>,
+}
+
+// the result of a split for the user that asked for it, the songs go out through SongView
+#[derive(Serialize)]
+pub struct SplitView<'a> {
+    #[serde(flatten)]
+    split: &'a Split,
+    songs: Vec<SongView<&'a Song>>,
+}
+
+impl Split {
+    pub fn view(&self, user: &User) -> SplitView<'_> {
+        SplitView {
+            split: self,
+            songs: covers::views(&self.songs, user),
+        }
+    }
+}
+
+// cut `parent` into one song per chapter of its info.json, the new songs are owned by `owner`
+// and with `playlist` collected into a playlist of theirs, the parent song itself is kept
+pub async fn split(
+    db: &mut PoolConnection<Postgres>,
+    parent: &Song,
+    owner: &str,
+    playlist: bool,
+) -> Result<Split, SplitError> {
+    if parent.parent.is_some() {
+        return Err(SplitError::IsChapter(parent.id.clone()));
+    }
+    let children = query!(
+        "select count(*) as \"count!\" from songs where parent = $1",
+        parent.id
+    )
+    .fetch_one(&mut *db)
+    .await?
+    .count;
+    if children > 0 {
+        return Err(SplitError::AlreadySplit(parent.id.clone()));
+    }
+    let chapters: Vec<Chapter> = VideoData::load_and_replace(&parent.id)
+        .ok()
+        .and_then(|x| x.chapters)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|x| x.end_time - x.start_time >= MIN_CHAPTER_SECONDS)
+        .collect();
+    if chapters.len() < 2 {
+        return Err(SplitError::NoChapters(parent.id.clone()));
+    }
+    let Some(user) = User::from_id(db, owner).await else {
+        return Err(SplitError::Other(anyhow::anyhow!(
+            "user {owner} does not exist"
+        )));
+    };
+    // every chapter is a new song, so they have to fit like any other download
+    Quota::check(db, &user, chapters.len(), false)
+        .await
+        .map_err(SplitError::Quota)?;
+    library::ensure_capacity(db, chapters.len())
+        .await
+        .map_err(SplitError::LibraryFull)?;
+    let mut songs = vec![];
+    let result = match cut_all(db, parent, &chapters, &user, &mut songs).await {
+        Ok(()) if playlist => create_playlist(db, parent, &songs, &user).await.map(Some),
+        Ok(()) => Ok(None),
+        Err(e) => Err(e),
+    };
+    let playlist = match result {
+        Ok(v) => v,
+        Err(e) => {
+            // leave nothing half split behind
+            let ids: Vec<String> = songs.iter().map(|x| x.id.clone()).collect();
+            if let Err(e) = Song::delete(db, &ids).await {
+                warn!("could not remove the chapters of {}: {e}", parent.id);
+            }
+            return Err(e);
+        }
+    };
+    SONG_SEARCH.get().await.write().await.update(db).await;
+    info!("split {} into {} chapters", parent.id, songs.len());
+    Ok(Split { songs, playlist })
+}
+
+async fn cut_all(
+    db: &mut PoolConnection<Postgres>,
+    parent: &Song,
+    chapters: &[Chapter],
+    user: &User,
+    songs: &mut Vec<Song>,
+) -> Result<(), SplitError> {
+    // a full album upload is named after the album
+    let album = if parent.album.is_empty() {
+        parent.title.clone()
+    } else {
+        parent.album.clone()
+    };
+    for (i, chapter) in chapters.iter().enumerate() {
+        let n = i as i32 + 1;
+        let path = format!("songs/{}-{n:02}.{}", parent.id, parent.format);
+        cut(&parent.id, &parent.format, &path, chapter).await?;
+        let mut song = match save_chapter(db, parent, chapter, n, &album, user, &path).await {
+            Ok(v) => v,
+            Err(e) => {
+                // the file has no row yet, so deleting the chapters would not find it
+                let _ = fs::remove_file(&path);
+                return Err(e);
+            }
+        };
+        song.analyze(db).await;
+        // the cut files carry no track number, the chapter order is the track order
+        song.track = Some(n);
+        let result = query!("update songs set track = $1 where id = $2", n, song.id)
+            .execute(&mut *db)
+            .await;
+        songs.push(song);
+        result?;
+    }
+    Ok(())
+}
+
+// tag and hash the cut file at `path` and add it as a song
+async fn save_chapter(
+    db: &mut PoolConnection<Postgres>,
+    parent: &Song,
+    chapter: &Chapter,
+    n: i32,
+    album: &str,
+    user: &User,
+    path: &str,
+) -> Result<Song, SplitError> {
+    let cleaned = titles::clean(&chapter.title, "", &parent.artist);
+    let song = Song {
+        id: format!("{}-{n:02}", parent.id),
+        default_search: format!("{} {} {album}", cleaned.title, cleaned.artist),
+        title: cleaned.title,
+        artist: cleaned.artist,
+        album: album.to_string(),
+        // there is nothing to fetch again, the parent has the source
+        url: String::new(),
+        duration: chapter.end_time.min(parent.duration) - chapter.start_time,
+        filesize: 0,
+        added_by: user.id.clone(),
+        last_played: 0,
+        added: unix_time(),
+        audio_hash: Some(hash(path.to_string()).await?),
+        loudness: None,
+        track_gain: None,
+        track_peak: None,
+        cover: None,
+        raw_title: chapter.title.clone(),
+        raw_artist: String::new(),
+        missing: false,
+        artist_id: None,
+        album_id: None,
+        track: None,
+        parent: Some(parent.id.clone()),
+        chapter: Some(n),
+        ..parent.clone()
+    };
+    if song.format == "mp3" {
+        let tags = AudioTags {
+            title: song.title.clone(),
+            artist: song.artist.clone(),
+            album: song.album.clone(),
+            genre: song.genre.clone(),
+            duration: song.duration,
+        };
+        tag(path.to_string(), tags).await?;
+    }
+    // measured after tagging, which rewrites the file
+    let song = Song {
+        filesize: fs::metadata(path).map_err(anyhow::Error::from)?.len() as i64,
+        ..song
+    };
+    song.save(db).await?;
+    Ok(song)
+}
+
+async fn hash(path: String) -> anyhow::Result<String> {
+    web::block(move || audio::content_hash(path)).await?
+}
+
+async fn tag(path: String, tags: AudioTags) -> anyhow::Result<()> {
+    web::block(move || audio::write_id3(path, &tags)).await?
+}
+
+// copy the chapter out of the parent file without re-encoding it
+async fn cut(parent: &str, format: &str, path: &str, chapter: &Chapter) -> Result<(), SplitError> {
+    let output = Command::new(FFMPEG)
+        .kill_on_drop(true)
+        .args([
+            "-y",
+            "-loglevel",
+            "error",
+            "-i",
+            &format!("songs/{parent}.{format}"),
+            "-ss",
+            &chapter.start_time.to_string(),
+            "-to",
+            &chapter.end_time.to_string(),
+            "-map",
+            "0",
+            "-map_chapters",
+            "-1",
+            "-c",
+            "copy",
+            path,
+        ])
+        .output()
+        .await
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => SplitError::NotInstalled,
+            _ => SplitError::Other(e.into()),
+        })?;
+    if !output.status.success() {
+        let _ = fs::remove_file(path);
+        return Err(SplitError::Failed(output.status.to_string()));
+    }
+    Ok(())
+}
+
+// playlist names are unique across users, fall back to one with the video id in it
+async fn create_playlist(
+    db: &mut PoolConnection<Postgres>,
+    parent: &Song,
+    songs: &[Song],
+    user: &User,
+) -> Result<String, SplitError> {
+    let title: String = parent.title.chars().take(80).collect();
+    let taken = query!(
+        "select count(*) as \"count!\" from playlist where name = $1",
+        title
+    )
+    .fetch_one(&mut *db)
+    .await?
+    .count
+        > 0;
+    let name = if taken {
+        format!("{title} ({})", parent.id)
+    } else {
+        title
+    };
+    let playlist = Playlist {
+        name: name.clone(),
+        public_playlist: true,
+        songs: songs.iter().map(|x| x.id.clone()).collect(),
+        author: user.username.clone(),
+        author_id: user.id.clone(),
+        edit_list: vec![],
+        description: format!("chapters of {}", parent.webpage_url),
+        likes: vec![],
+        cover: String::new(),
+        duration: (songs.iter().map(|x| x.duration).sum::<f64>() + 0.5) as i64,
+        lastupdate: time!(),
+    };
+    playlist.insert(db).await?;
+    Ok(name)
+}
@@ -0,0 +1,184 @@
+use crate::{
+    audio,
+    types::{Song, User},
+};
+use anyhow::Result;
+use derive_more::Display;
+use image::{codecs::jpeg::JpegEncoder, imageops::FilterType};
+use serde::{Deserialize, Serialize, Serializer};
+use std::{
+    borrow::Borrow,
+    fs::{self, File},
+};
+
+const JPEG_QUALITY: u8 = 85;
+
+#[derive(Deserialize, Display, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CoverSize {
+    #[display(fmt = "small")]
+    Small,
+    #[default]
+    #[display(fmt = "medium")]
+    Medium,
+    #[display(fmt = "large")]
+    Large,
+}
+
+impl CoverSize {
+    const ALL: [Self; 3] = [Self::Small, Self::Medium, Self::Large];
+
+    // longest side in pixels, art smaller than this is never scaled up
+    fn pixels(self) -> u32 {
+        match self {
+            Self::Small => 96,
+            Self::Medium => 300,
+            Self::Large => 800,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CoverQuery {
+    pub size: Option<CoverSize>,
+}
+
+pub fn cover_path(id: &str, size: CoverSize) -> String {
+    format!("songs/{id}.cover-{size}.jpg")
+}
+
+// pull the embedded art out of a song and write every size next to it, returns false when the
+// file has no art, blocking so run it on the threadpool
+pub fn extract(id: &str, format: &str) -> Result<bool> {
+    let Some(data) = audio::cover_art(format!("songs/{id}.{format}"))? else {
+        return Ok(false);
+    };
+    let art = image::load_from_memory(&data)?;
+    for size in CoverSize::ALL {
+        let pixels = size.pixels();
+        let resized = if art.width() > pixels || art.height() > pixels {
+            art.resize(pixels, pixels, FilterType::Lanczos3)
+        } else {
+            art.clone()
+        };
+        let file = File::create(cover_path(id, size))?;
+        JpegEncoder::new_with_quality(file, JPEG_QUALITY).encode_image(&resized.to_rgb8())?;
+    }
+    Ok(true)
+}
+
+pub fn remove(id: &str) {
+    for size in CoverSize::ALL {
+        let _ = fs::remove_file(cover_path(id, size));
+    }
+}
+
+// a song as one user gets to see it, Song leaves its artwork fields out and only this puts
+// them back when the user did not turn thumbnails off, every response or event with songs in
+// it goes through here
+#[derive(Clone)]
+pub struct SongView<S> {
+    pub song: S,
+    pub artwork: bool,
+}
+
+impl<S: Borrow<Song>> SongView<S> {
+    pub fn new(song: S, user: &User) -> Self {
+        Self {
+            song,
+            artwork: user.thumbnails,
+        }
+    }
+}
+
+impl<S: Borrow<Song>> Serialize for SongView<S> {
+    fn serialize<T: Serializer>(&self, serializer: T) -> Result<T::Ok, T::Error> {
+        #[derive(Serialize)]
+        struct Fields<'a> {
+            #[serde(flatten)]
+            song: &'a Song,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            thumbnail: Option<&'a str>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            cover: Option<Option<bool>>,
+        }
+        let song = self.song.borrow();
+        Fields {
+            song,
+            thumbnail: self.artwork.then_some(song.thumbnail.as_str()),
+            cover: self.artwork.then_some(song.cover),
+        }
+        .serialize(serializer)
+    }
+}
+
+// the songs of a response for `user`
+pub fn views<'a>(
+    songs: impl IntoIterator<Item = &'a Song>,
+    user: &User,
+) -> Vec<SongView<&'a Song>> {
+    songs.into_iter().map(|x| SongView::new(x, user)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::tests::song;
+
+    fn json<T: Serialize>(value: &T) -> serde_json::Value {
+        serde_json::from_str(&serde_json::to_string(value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn artwork_only_through_view() {
+        let mut song = song("a", "title", "artist", 200.0);
+        song.thumbnail = "https://example.com/a.jpg".to_string();
+        song.cover = Some(true);
+        let shown = json(&SongView {
+            song: &song,
+            artwork: true,
+        });
+        assert_eq!(shown["id"], "a");
+        assert_eq!(shown["thumbnail"], "https://example.com/a.jpg");
+        assert_eq!(shown["cover"], true);
+        let hidden = json(&SongView {
+            song: &song,
+            artwork: false,
+        });
+        assert_eq!(hidden["id"], "a");
+        assert!(hidden.get("thumbnail").is_none());
+        assert!(hidden.get("cover").is_none());
+        // a song serialized without a view never carries artwork
+        let raw = json(&song);
+        assert!(raw.get("thumbnail").is_none());
+        assert!(raw.get("cover").is_none());
+    }
+
+    #[test]
+    fn events_follow_the_view() {
+        let mut song = song("a", "title", "artist", 200.0);
+        song.thumbnail = "https://example.com/a.jpg".to_string();
+        let event = crate::events::JobEvent::SongAdded {
+            job: 1,
+            song: SongView {
+                song: Box::new(song),
+                artwork: false,
+            },
+        };
+        let event = json(&event);
+        assert_eq!(event["event"], "song_added");
+        assert_eq!(event["song"]["id"], "a");
+        assert!(event["song"].get("thumbnail").is_none());
+    }
+
+    #[test]
+    fn unchecked_cover_is_null() {
+        let song = song("a", "title", "artist", 200.0);
+        let shown = json(&SongView {
+            song: Box::new(song),
+            artwork: true,
+        });
+        assert_eq!(shown["cover"], serde_json::Value::Null);
+        assert_eq!(shown["thumbnail"], "");
+    }
+}
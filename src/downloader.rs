@@ -0,0 +1,154 @@
+use crate::{
+    covers::SongView,
+    events::JobEvent,
+    library,
+    quota::Quota,
+    types::{DownloadJob, JobKind, PlaylistImport, Song, User},
+    CONFIG, DB, DOWNLOAD_NOTIFY, SESSIONS,
+};
+use actix_web::rt::{self, task::JoinError};
+use futures::future::{select_all, LocalBoxFuture};
+use log::{error, info, warn};
+use sqlx::{pool::PoolConnection, Postgres};
+use std::time::Duration;
+
+// how long a worker waits before asking for a database connection again
+const ACQUIRE_RETRY: Duration = Duration::from_secs(5);
+
+// spawn the download workers and restart any of them that panic or exit, the number of workers
+// is the amount of songs that can be downloaded at once
+pub async fn supervise() {
+    if let Ok(mut db) = DB.get().await.db.acquire().await {
+        match DownloadJob::requeue_running(&mut db).await {
+            Ok(0) => (),
+            Ok(n) => info!("requeued {n} download jobs that were interrupted"),
+            Err(e) => error!("failed to requeue interrupted download jobs: {e}"),
+        }
+    }
+    let workers = CONFIG.download_workers.max(1);
+    let mut handles: Vec<_> = (0..workers).map(spawn_worker).collect();
+    info!("started {workers} download workers");
+    loop {
+        let ((id, result), _, remaining) = select_all(handles).await;
+        match result {
+            Err(e) if e.is_panic() => error!("download worker {id} panicked, restarting"),
+            _ => warn!("download worker {id} exited, restarting"),
+        }
+        handles = remaining;
+        handles.push(spawn_worker(id));
+    }
+}
+
+fn spawn_worker(id: usize) -> LocalBoxFuture<'static, (usize, Result<(), JoinError>)> {
+    let handle = rt::spawn(worker(id));
+    Box::pin(async move { (id, handle.await) })
+}
+
+async fn worker(id: usize) {
+    loop {
+        let mut db = match DB.get().await.db.acquire().await {
+            Ok(v) => v,
+            Err(e) => {
+                // pending jobs stay pending, so a notify might never come, try again later
+                error!("worker {id} could not acquire a database connection: {e}");
+                rt::time::sleep(ACQUIRE_RETRY).await;
+                continue;
+            }
+        };
+        let job = match DownloadJob::claim(&mut db).await {
+            Ok(Some(v)) => v,
+            Ok(None) => {
+                // give the connection back while we sleep
+                drop(db);
+                DOWNLOAD_NOTIFY.notified().await;
+                continue;
+            }
+            Err(e) => {
+                error!("worker {id} failed to claim a download job: {e}");
+                // jobs may be waiting already, nothing would wake a worker up for them
+                drop(db);
+                rt::time::sleep(ACQUIRE_RETRY).await;
+                continue;
+            }
+        };
+        let (job_id, requester) = (job.id, job.requester.clone());
+        let progress = move |progress| {
+            let event = JobEvent::Progress {
+                job: job_id,
+                progress,
+            };
+            SESSIONS.lock().unwrap().broadcast(&requester, event);
+        };
+        let kind = JobKind::parse(&job.kind);
+        let song = match (kind, job.song.as_deref()) {
+            (JobKind::Download, _) => match check_limits(&mut db, &job.requester).await {
+                Ok(()) => Song::from_url(
+                    &job.url,
+                    &mut db,
+                    job.requester.clone(),
+                    job.import_id,
+                    progress,
+                )
+                .await
+                .map_err(|e| e.to_string()),
+                Err(e) => Err(e),
+            },
+            (_, Some(song)) => Song::refetch(&mut db, song, kind == JobKind::Redownload, progress)
+                .await
+                .map_err(|e| e.to_string()),
+            (_, None) => Err(format!("{kind} job without a song")),
+        };
+        let (result, event) = match song {
+            Ok(song) => {
+                info!(
+                    "worker {id} finished {kind} of {} from {}",
+                    song.id, job.url
+                );
+                let result = job.finish(&mut db, &song.id).await;
+                // the events only go to the requester, so their thumbnail setting applies
+                let song = SongView {
+                    song: Box::new(song),
+                    artwork: User::from_id(&mut db, &job.requester)
+                        .await
+                        .is_some_and(|x| x.thumbnails),
+                };
+                let event = match kind {
+                    JobKind::Download => JobEvent::SongAdded { job: job_id, song },
+                    _ => JobEvent::SongUpdated { job: job_id, song },
+                };
+                (result, event)
+            }
+            Err(error) => {
+                error!("worker {id} failed to download {}: {error}", job.url);
+                let result = job.fail(&mut db, &error).await;
+                (result, JobEvent::Failed { job: job.id, error })
+            }
+        };
+        SESSIONS.lock().unwrap().broadcast(&job.requester, event);
+        if let Some(import) = job.import_id {
+            if let Err(e) = PlaylistImport::finish_if_done(&mut db, import).await {
+                error!("worker {id} could not finish playlist import {import}: {e}");
+            }
+        }
+        if let Err(e) = result {
+            error!(
+                "worker {id} could not record the result of job {}: {e}",
+                job.id
+            );
+        }
+    }
+}
+
+// check the limits again before a download, the library or the requester's quota could have
+// filled up with the jobs that ran since this one was queued
+async fn check_limits(db: &mut PoolConnection<Postgres>, requester: &str) -> Result<(), String> {
+    let Some(user) = User::from_id(db, requester).await else {
+        return Err(format!("user {requester} no longer exists"));
+    };
+    Quota::check(db, &user, 1, false)
+        .await
+        .map_err(|e| e.to_string())?;
+    library::ensure_capacity(db, 1)
+        .await
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,111 @@
+use crate::{covers::SongView, extractors::Claims, types::Song, youtube::Progress, SESSIONS};
+use actix::{Actor, ActorContext, AsyncContext, Handler, Message, Recipient, StreamHandler};
+use actix_web::{get, web, Error as ActixError, HttpRequest, HttpResponse};
+use actix_web_actors::ws;
+use serde::Serialize;
+use std::collections::HashMap;
+
+// events sent to every websocket connection of the user that requested the download
+#[derive(Message, Serialize, Clone)]
+#[rtype(result = "()")]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum JobEvent {
+    Progress {
+        job: i64,
+        #[serde(flatten)]
+        progress: Progress,
+    },
+    SongAdded {
+        job: i64,
+        song: SongView<Box<Song>>,
+    },
+    // a refresh or redownload finished
+    SongUpdated {
+        job: i64,
+        song: SongView<Box<Song>>,
+    },
+    Failed {
+        job: i64,
+        error: String,
+    },
+}
+
+#[derive(Default)]
+pub struct Sessions(HashMap<String, Vec<Recipient<JobEvent>>>);
+
+impl Sessions {
+    pub fn register(&mut self, user: String, session: Recipient<JobEvent>) {
+        self.0.entry(user).or_default().push(session);
+    }
+    // `session` is still connected while its actor stops, so it is removed by identity
+    pub fn unregister(&mut self, user: &str, session: &Recipient<JobEvent>) {
+        if let Some(sessions) = self.0.get_mut(user) {
+            sessions.retain(|x| x != session && x.connected());
+            if sessions.is_empty() {
+                self.0.remove(user);
+            }
+        }
+    }
+    pub fn broadcast(&self, user: &str, event: JobEvent) {
+        if let Some(sessions) = self.0.get(user) {
+            for session in sessions {
+                session.do_send(event.clone());
+            }
+        }
+    }
+}
+
+struct EventSocket {
+    user: String,
+}
+
+impl Actor for EventSocket {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        SESSIONS
+            .lock()
+            .unwrap()
+            .register(self.user.clone(), ctx.address().recipient());
+    }
+
+    fn stopped(&mut self, ctx: &mut Self::Context) {
+        SESSIONS
+            .lock()
+            .unwrap()
+            .unregister(&self.user, &ctx.address().recipient());
+    }
+}
+
+impl Handler<JobEvent> for EventSocket {
+    type Result = ();
+
+    fn handle(&mut self, msg: JobEvent, ctx: &mut Self::Context) {
+        if let Ok(v) = serde_json::to_string(&msg) {
+            ctx.text(v);
+        }
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for EventSocket {
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        match msg {
+            Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
+            Ok(ws::Message::Close(reason)) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            Err(_) => ctx.stop(),
+            _ => (),
+        }
+    }
+}
+
+#[get("/events")]
+pub async fn events(
+    claims: Claims,
+    req: HttpRequest,
+    stream: web::Payload,
+) -> Result<HttpResponse, ActixError> {
+    ws::start(EventSocket { user: claims.sub }, &req, stream)
+}
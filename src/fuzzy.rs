@@ -8,6 +8,7 @@ pub enum SearchType {
     Default,
     User,
     Id,
+    Lyrics,
 }
 
 pub trait FuzzyComparable<'a> {
@@ -23,6 +24,7 @@ impl<'a> FuzzyComparable<'a> for Song {
             S::Default => &self.default_search,
             S::User => "",
             S::Id => "",
+            S::Lyrics => "",
         }
     }
 }
@@ -43,18 +45,6 @@ impl<'a> FuzzyComparable<'a> for Playlist {
 // I don't need all the crate and I also want to be able to tweak the code without an additional
 // repo
 
-pub fn fuzzy_search_best_n<'a, T: FuzzyComparable<'a>>(
-    s: &str,
-    list: &'a [T],
-    n: usize,
-    st: &SearchType,
-) -> Vec<(&'a T, f32)> {
-    fuzzy_search_sorted(s, list, st)
-        .into_iter()
-        .take(n)
-        .collect()
-}
-
 pub fn fuzzy_search_sorted<'a, T: FuzzyComparable<'a>>(
     s: &str,
     list: &'a [T],
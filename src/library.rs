@@ -0,0 +1,288 @@
+use crate::{
+    types::{JobState, Song},
+    CONFIG, SONG_SEARCH,
+};
+use actix_web::web;
+use anyhow::Result;
+use derive_more::Display;
+use log::{error, info};
+use sqlx::{pool::PoolConnection, query, Postgres};
+use std::{collections::HashMap, fs, io, path::Path};
+
+const GB: u64 = 1024 * 1024 * 1024;
+
+#[derive(Debug, Display)]
+pub enum LimitError {
+    #[display(fmt = "the library is full, it can hold at most {} songs", _0)]
+    TooManySongs(usize),
+    #[display(fmt = "the songs folder is full, it can use at most {} GB", _0)]
+    FolderFull(usize),
+    #[display(fmt = "could not check library limits: {}", _0)]
+    Unavailable(String),
+}
+
+impl std::error::Error for LimitError {}
+
+pub struct Usage {
+    pub songs: i64,
+    pub bytes: u64,
+}
+
+impl Usage {
+    pub async fn current(db: &mut PoolConnection<Postgres>) -> Result<Self> {
+        let songs = query!("select count(*) from songs")
+            .fetch_one(db)
+            .await?
+            .count
+            .unwrap_or_default();
+        let bytes = web::block(|| folder_size("songs")).await??;
+        Ok(Self { songs, bytes })
+    }
+
+    // whether `count` more songs fit, their size is only known once they are downloaded
+    fn check(&self, count: usize) -> Result<(), LimitError> {
+        if self.songs + count as i64 > CONFIG.max_songs as i64 {
+            return Err(LimitError::TooManySongs(CONFIG.max_songs));
+        }
+        if self.bytes >= CONFIG.max_song_folder_size_gb as u64 * GB {
+            return Err(LimitError::FolderFull(CONFIG.max_song_folder_size_gb));
+        }
+        Ok(())
+    }
+}
+
+pub fn folder_size(path: impl AsRef<Path>) -> io::Result<u64> {
+    if !path.as_ref().exists() {
+        return Ok(0);
+    }
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        let metadata = entry?.metadata()?;
+        if metadata.is_file() {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
+// make sure there is room for `count` more songs, evicting old songs first if that is enabled
+pub async fn ensure_capacity(
+    db: &mut PoolConnection<Postgres>,
+    count: usize,
+) -> Result<(), LimitError> {
+    let usage = Usage::current(db)
+        .await
+        .map_err(|e| LimitError::Unavailable(e.to_string()))?;
+    match usage.check(count) {
+        Err(e) if CONFIG.evict_unused_songs => {
+            match evict(db, &usage, count).await {
+                Ok(0) => return Err(e),
+                Ok(n) => info!("evicted {n} songs to make room"),
+                Err(e) => error!("failed to evict songs: {e}"),
+            }
+            Usage::current(db)
+                .await
+                .map_err(|e| LimitError::Unavailable(e.to_string()))?
+                .check(count)
+        }
+        v => v,
+    }
+}
+
+// remove the least recently played or added songs that are not in any playlist and that nobody
+// likes until `count` more songs fit, songs that queued jobs or unfinished playlist imports are
+// still working with are kept, returns how many songs were removed
+pub async fn evict(
+    db: &mut PoolConnection<Postgres>,
+    usage: &Usage,
+    count: usize,
+) -> Result<usize> {
+    let candidates = query!(
+        r#"select id, format, filesize from songs
+        where id not in (select unnest(songs) from playlist)
+          and id not in (select unnest(likes) from users)
+          and id not in (select unnest(songs) from playlist_imports where not finished)
+          and id not in (select song from download_jobs where song is not null and (
+              state in ($1, $2)
+              or import_id in (select id from playlist_imports where not finished)))
+        order by greatest(last_played, added) asc"#,
+        JobState::Pending.to_string(),
+        JobState::Running.to_string()
+    )
+    .fetch_all(&mut *db)
+    .await?;
+    let max_bytes = CONFIG.max_song_folder_size_gb as u64 * GB;
+    let (mut songs, mut bytes) = (usage.songs, usage.bytes);
+    let mut evicted = 0;
+    for song in candidates {
+        if songs + (count as i64) <= CONFIG.max_songs as i64 && bytes < max_bytes {
+            break;
+        }
+        let freed = fs::metadata(format!("songs/{}.{}", song.id, song.format))
+            .map(|x| x.len())
+            .unwrap_or(0);
+        Song::delete(db, std::slice::from_ref(&song.id)).await?;
+        info!("evicted {} ({} bytes)", song.id, song.filesize);
+        songs -= 1;
+        bytes = bytes.saturating_sub(freed);
+        evicted += 1;
+    }
+    if evicted > 0 {
+        SONG_SEARCH.get().await.write().await.update(db).await;
+    }
+    Ok(evicted)
+}
+
+// how far apart two durations can be for the songs to still count as the same track
+const DUPLICATE_DURATION_SLACK: f64 = 3.0;
+
+fn normalize(s: &str) -> String {
+    s.chars()
+        .filter(|x| x.is_alphanumeric())
+        .flat_map(|x| x.to_lowercase())
+        .collect()
+}
+
+// songs that are probably the same track uploaded more than once, grouped by normalized title
+// and artist where the durations are within a few seconds of each other
+pub fn near_duplicates(songs: &[Song]) -> Vec<Vec<&Song>> {
+    let mut groups: HashMap<(String, String), Vec<&Song>> = HashMap::new();
+    for song in songs {
+        groups
+            .entry((normalize(&song.title), normalize(&song.artist)))
+            .or_default()
+            .push(song);
+    }
+    let mut duplicates = vec![];
+    for (_, mut group) in groups {
+        group.sort_by(|a, b| a.duration.total_cmp(&b.duration));
+        let mut cluster: Vec<&Song> = vec![];
+        for song in group {
+            match cluster.last() {
+                Some(last) if song.duration - last.duration > DUPLICATE_DURATION_SLACK => {
+                    if cluster.len() > 1 {
+                        duplicates.push(cluster);
+                    }
+                    cluster = vec![song];
+                }
+                _ => cluster.push(song),
+            }
+        }
+        if cluster.len() > 1 {
+            duplicates.push(cluster);
+        }
+    }
+    duplicates
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn song(id: &str, title: &str, artist: &str, duration: f64) -> Song {
+        Song {
+            id: id.to_string(),
+            title: title.to_string(),
+            uploader: String::new(),
+            artist: artist.to_string(),
+            genre: String::new(),
+            album: String::new(),
+            url: String::new(),
+            duration,
+            age_limit: 0,
+            webpage_url: String::new(),
+            was_live: false,
+            upload_date: String::new(),
+            filesize: 0,
+            added_by: String::new(),
+            default_search: String::new(),
+            format: String::from("mp3"),
+            last_played: 0,
+            added: 0,
+            audio_hash: None,
+            loudness: None,
+            track_gain: None,
+            track_peak: None,
+            thumbnail: String::new(),
+            cover: None,
+            raw_title: String::new(),
+            raw_artist: String::new(),
+            raw_uploader: String::new(),
+            missing: false,
+            artist_id: None,
+            album_id: None,
+            track: None,
+            parent: None,
+            chapter: None,
+        }
+    }
+
+    // the ids of every group, sorted since the groups come out of a hash map
+    fn groups(songs: &[Song]) -> Vec<Vec<&str>> {
+        let mut groups: Vec<Vec<&str>> = near_duplicates(songs)
+            .into_iter()
+            .map(|x| {
+                let mut ids: Vec<&str> = x.iter().map(|x| x.id.as_str()).collect();
+                ids.sort();
+                ids
+            })
+            .collect();
+        groups.sort();
+        groups
+    }
+
+    #[test]
+    fn same_track_different_spelling() {
+        let songs = [
+            song("a", "Never Gonna Give You Up", "Rick Astley", 213.0),
+            song("b", "never gonna give you up!", "RICK ASTLEY", 214.5),
+            song("c", "Never  Gonna Give-You Up", "Rick Astley", 212.0),
+        ];
+        assert_eq!(groups(&songs), [["a", "b", "c"]]);
+    }
+
+    #[test]
+    fn durations_too_far_apart() {
+        let songs = [
+            song("radio", "Song", "Artist", 180.0),
+            song("radio2", "Song", "Artist", 182.0),
+            song("extended", "Song", "Artist", 300.0),
+            song("live", "Song", "Artist", 302.5),
+            song("alone", "Song", "Artist", 240.0),
+        ];
+        assert_eq!(
+            groups(&songs),
+            [vec!["extended", "live"], vec!["radio", "radio2"]]
+        );
+    }
+
+    #[test]
+    fn chained_durations_stay_together() {
+        // each step is within the slack even though the ends are not
+        let songs = [
+            song("a", "Song", "Artist", 100.0),
+            song("b", "Song", "Artist", 102.5),
+            song("c", "Song", "Artist", 105.0),
+        ];
+        assert_eq!(groups(&songs), [["a", "b", "c"]]);
+    }
+
+    #[test]
+    fn different_tracks() {
+        let songs = [
+            song("a", "Song", "Artist", 200.0),
+            song("b", "Song", "Other Artist", 200.0),
+            song("c", "Other Song", "Artist", 200.0),
+            song("d", "Song (Remix)", "Artist", 200.0),
+        ];
+        assert!(groups(&songs).is_empty());
+        assert!(near_duplicates(&[]).is_empty());
+    }
+
+    #[test]
+    fn normalizes_unicode() {
+        assert_eq!(normalize("Beyoncé — Halo"), "beyoncéhalo");
+        assert_eq!(normalize("ÄÖÜ 123"), "äöü123");
+        assert_eq!(normalize(" - !? "), "");
+    }
+}
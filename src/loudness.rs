@@ -0,0 +1,274 @@
+use crate::{audio, types::Song};
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::{f64::consts::PI, path::Path};
+
+// replaygain 2.0 reference level
+pub const REFERENCE_LUFS: f64 = -18.0;
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+
+#[derive(Serialize)]
+pub struct Loudness {
+    // integrated loudness in LUFS
+    pub integrated: f64,
+    // highest absolute sample value, 1.0 is full scale
+    pub peak: f64,
+}
+
+impl Loudness {
+    pub fn gain(&self) -> f64 {
+        gain(self.integrated)
+    }
+}
+
+pub fn gain(loudness: f64) -> f64 {
+    REFERENCE_LUFS - loudness
+}
+
+// second order iir filter in direct form 1
+#[derive(Clone, Default)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, input: f64) -> f64 {
+        let output = self.b[0] * input + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[1] * self.y[0]
+            - self.a[2] * self.y[1];
+        self.x = [input, self.x[0]];
+        self.y = [output, self.y[0]];
+        output
+    }
+}
+
+// the two stage k-weighting filter from ITU-R BS.1770, coefficients are derived for any sample
+// rate the same way libebur128 does it
+fn k_weighting(rate: u32) -> [Biquad; 2] {
+    let rate = rate as f64;
+
+    let f0 = 1681.974450955533;
+    let g = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(g / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        ..Default::default()
+    };
+
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        ..Default::default()
+    };
+    [shelf, high_pass]
+}
+
+fn to_lufs(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+fn gated_loudness(blocks: &[f64]) -> Option<f64> {
+    let absolute: Vec<f64> = blocks
+        .iter()
+        .copied()
+        .filter(|x| to_lufs(*x) > ABSOLUTE_GATE)
+        .collect();
+    if absolute.is_empty() {
+        return None;
+    }
+    let threshold = to_lufs(absolute.iter().sum::<f64>() / absolute.len() as f64) + RELATIVE_GATE;
+    let relative: Vec<f64> = absolute
+        .into_iter()
+        .filter(|x| to_lufs(*x) > threshold)
+        .collect();
+    if relative.is_empty() {
+        return None;
+    }
+    Some(to_lufs(
+        relative.iter().sum::<f64>() / relative.len() as f64,
+    ))
+}
+
+// integrated loudness and sample peak of a file, blocking so run it on the threadpool
+pub fn analyze(path: impl AsRef<Path>) -> Result<Loudness> {
+    let mut filters: Vec<[Biquad; 2]> = vec![];
+    // mean square of every 100ms step, a 400ms block is four of them
+    let mut steps: Vec<f64> = vec![];
+    let (mut step_sum, mut step_len, mut step_size) = (0.0, 0, 0);
+    let mut peak: f64 = 0.0;
+    audio::decode(path, |samples, channels, rate| {
+        if filters.len() != channels {
+            filters = vec![k_weighting(rate); channels];
+            step_size = (rate / 10) as usize;
+        }
+        for frame in samples.chunks_exact(channels) {
+            for (sample, [shelf, high_pass]) in frame.iter().zip(filters.iter_mut()) {
+                let sample = *sample as f64;
+                peak = peak.max(sample.abs());
+                let weighted = high_pass.process(shelf.process(sample));
+                step_sum += weighted * weighted;
+            }
+            step_len += 1;
+            if step_len == step_size {
+                steps.push(step_sum / step_size as f64);
+                step_sum = 0.0;
+                step_len = 0;
+            }
+        }
+    })?;
+    let blocks: Vec<f64> = steps
+        .windows(4)
+        .map(|x| x.iter().sum::<f64>() / 4.0)
+        .collect();
+    let integrated = gated_loudness(&blocks).ok_or_else(|| anyhow!("track is silent"))?;
+    Ok(Loudness { integrated, peak })
+}
+
+// loudness of a group of tracks (an album or playlist) from the loudness of each track weighted
+// by its duration, this skips the cross track gating so it can be done without decoding again
+pub fn combined(tracks: &[(f64, f64)]) -> Option<f64> {
+    let duration: f64 = tracks.iter().map(|(_, duration)| duration).sum();
+    if duration <= 0.0 {
+        return None;
+    }
+    let power: f64 = tracks
+        .iter()
+        .map(|(loudness, duration)| 10f64.powf((loudness + 0.691) / 10.0) * duration)
+        .sum();
+    Some(to_lufs(power / duration))
+}
+
+#[derive(Serialize)]
+pub struct GroupGain {
+    pub loudness: f64,
+    pub gain: f64,
+    pub peak: f64,
+    // how many of the songs had been analyzed
+    pub tracks: usize,
+}
+
+pub fn group_gain<'a>(songs: impl IntoIterator<Item = &'a Song>) -> Option<GroupGain> {
+    let mut tracks = vec![];
+    let mut peak: f64 = 0.0;
+    for song in songs {
+        if let Some(loudness) = song.loudness {
+            tracks.push((loudness, song.duration));
+            peak = peak.max(song.track_peak.unwrap_or_default());
+        }
+    }
+    let loudness = combined(&tracks)?;
+    Some(GroupGain {
+        loudness,
+        gain: gain(loudness),
+        peak,
+        tracks: tracks.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const RATE: u32 = 48000;
+
+    // 16 bit pcm wav with every channel playing the same samples
+    fn write_wav(name: &str, channels: u16, samples: &[f64]) -> std::path::PathBuf {
+        let data_len = (samples.len() * channels as usize * 2) as u32;
+        let mut wav = vec![];
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&channels.to_le_bytes());
+        wav.extend_from_slice(&RATE.to_le_bytes());
+        wav.extend_from_slice(&(RATE * channels as u32 * 2).to_le_bytes());
+        wav.extend_from_slice(&(channels * 2).to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        for sample in samples {
+            let value = (sample * i16::MAX as f64).round() as i16;
+            for _ in 0..channels {
+                wav.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        let path = std::env::temp_dir().join(format!("seanify-loudness-{name}.wav"));
+        fs::write(&path, wav).unwrap();
+        path
+    }
+
+    fn sine(frequency: f64, amplitude: f64, seconds: f64) -> Vec<f64> {
+        (0..(RATE as f64 * seconds) as usize)
+            .map(|i| amplitude * (2.0 * PI * frequency * i as f64 / RATE as f64).sin())
+            .collect()
+    }
+
+    #[test]
+    fn sine_at_known_level() {
+        // a 1 kHz sine at -20 dBFS in one channel measures -23 LUFS, k-weighting is close to
+        // flat at 1 kHz
+        let path = write_wav("mono", 1, &sine(1000.0, 0.1, 5.0));
+        let loudness = analyze(&path).unwrap();
+        let _ = fs::remove_file(path);
+        assert!(
+            (loudness.integrated + 23.01).abs() < 0.1,
+            "{}",
+            loudness.integrated
+        );
+        assert!((loudness.peak - 0.1).abs() < 0.001, "{}", loudness.peak);
+        assert!((loudness.gain() - 5.01).abs() < 0.1);
+
+        // the same signal in both channels adds up to 3 dB more
+        let path = write_wav("stereo", 2, &sine(1000.0, 0.1, 5.0));
+        let loudness = analyze(&path).unwrap();
+        let _ = fs::remove_file(path);
+        assert!(
+            (loudness.integrated + 20.0).abs() < 0.1,
+            "{}",
+            loudness.integrated
+        );
+    }
+
+    #[test]
+    fn silence() {
+        let path = write_wav("silence", 2, &vec![0.0; RATE as usize * 3]);
+        let result = analyze(&path);
+        let _ = fs::remove_file(path);
+        assert!(result.is_err());
+        // quieter than the absolute gate counts as silent too
+        let path = write_wav("quiet", 1, &sine(1000.0, 0.0002, 3.0));
+        let result = analyze(&path);
+        let _ = fs::remove_file(path);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn combined_loudness() {
+        assert_eq!(combined(&[]), None);
+        assert_eq!(combined(&[(-14.0, 0.0)]), None);
+        let same = combined(&[(-14.0, 100.0), (-14.0, 300.0)]).unwrap();
+        assert!((same + 14.0).abs() < 1e-9);
+        // equal durations 10 LU apart are dominated by the louder track
+        let mixed = combined(&[(-10.0, 60.0), (-20.0, 60.0)]).unwrap();
+        assert!((mixed + 12.6).abs() < 0.01, "{mixed}");
+    }
+}
@@ -0,0 +1,274 @@
+use crate::fuzzy::{FuzzyComparable, SearchType};
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::{pool::PoolConnection, query, query_as, Postgres};
+use std::fs;
+
+// a single line of synced lyrics, `time` is in seconds from the start of the song
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct LrcLine {
+    pub time: f64,
+    pub text: String,
+}
+
+// parse an lrc file, a line can carry several timestamps and [offset:ms] shifts all of them
+pub fn parse_lrc(lrc: &str) -> Vec<LrcLine> {
+    let mut offset = 0.0;
+    let mut lines = vec![];
+    for line in lrc.lines() {
+        let mut rest = line.trim();
+        let mut times = vec![];
+        while let Some(tag) = rest.strip_prefix('[') {
+            let Some((tag, tail)) = tag.split_once(']') else {
+                break;
+            };
+            if let Some(ms) = tag.strip_prefix("offset:") {
+                offset = ms.trim().parse::<f64>().unwrap_or_default() / 1000.0;
+            } else if let Some(time) = parse_timestamp(tag) {
+                times.push(time);
+            }
+            rest = tail;
+        }
+        let text = rest.trim();
+        for time in times {
+            lines.push(LrcLine {
+                time,
+                text: text.to_string(),
+            });
+        }
+    }
+    // a positive offset means the lyrics should show up sooner
+    for line in &mut lines {
+        line.time = (line.time - offset).max(0.0);
+    }
+    lines.sort_by(|a, b| a.time.total_cmp(&b.time));
+    lines
+}
+
+// mm:ss, mm:ss.xx or mm:ss:xx
+fn parse_timestamp(tag: &str) -> Option<f64> {
+    let (minutes, seconds) = tag.split_once(':')?;
+    let minutes = minutes.parse::<u64>().ok()?;
+    let seconds = match seconds.split_once(':') {
+        Some((seconds, fraction)) => format!("{seconds}.{fraction}"),
+        None => seconds.to_string(),
+    };
+    let seconds = seconds.parse::<f64>().ok()?;
+    Some(minutes as f64 * 60.0 + seconds)
+}
+
+#[derive(Serialize)]
+pub struct Lyrics {
+    pub song: String,
+    pub plain: String,
+    // raw lrc, only set when the lyrics have timestamps
+    pub synced: Option<String>,
+    // upload or subtitles
+    pub source: String,
+    pub updated: i64,
+}
+
+// what /songs/{song}/lyrics returns as json
+#[derive(Serialize)]
+pub struct ParsedLyrics {
+    pub plain: String,
+    pub lines: Vec<LrcLine>,
+    pub source: String,
+    pub updated: i64,
+}
+
+impl Lyrics {
+    // plain text or lrc, lrc is detected by the timestamps and the plain text is derived from it
+    pub fn from_text(song: &str, text: &str, source: &str) -> Self {
+        let lines = parse_lrc(text);
+        let (plain, synced) = if lines.is_empty() {
+            (text.trim().to_string(), None)
+        } else {
+            let plain: Vec<&str> = lines
+                .iter()
+                .map(|x| x.text.as_str())
+                .filter(|x| !x.is_empty())
+                .collect();
+            (plain.join("\n"), Some(text.to_string()))
+        };
+        Self {
+            song: song.to_string(),
+            plain,
+            synced,
+            source: source.to_string(),
+            updated: 0,
+        }
+    }
+
+    pub fn parsed(&self) -> ParsedLyrics {
+        ParsedLyrics {
+            plain: self.plain.clone(),
+            lines: self.synced.as_deref().map(parse_lrc).unwrap_or_default(),
+            source: self.source.clone(),
+            updated: self.updated,
+        }
+    }
+
+    pub async fn for_song(db: &mut PoolConnection<Postgres>, id: &str) -> Result<Option<Self>> {
+        Ok(
+            query_as!(Lyrics, "select * from lyrics where song = $1", id)
+                .fetch_optional(db)
+                .await?,
+        )
+    }
+
+    pub async fn save(&self, db: &mut PoolConnection<Postgres>) -> Result<()> {
+        query!(
+            r#"insert into lyrics(song, plain, synced, source, updated)
+            values($1, $2, $3, $4, extract(epoch from now())::bigint)
+            on conflict (song) do update
+            set plain = $2, synced = $3, source = $4, updated = extract(epoch from now())::bigint"#,
+            self.song,
+            self.plain,
+            self.synced,
+            self.source
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    // pick up the subtitles the extractor converted to songs/{id}.{lang}.lrc and store them for
+    // `song`, which differs from `id` when the download turned out to be a duplicate, uploaded
+    // lyrics are never replaced by subtitles
+    pub async fn import_subtitles(
+        db: &mut PoolConnection<Postgres>,
+        id: &str,
+        song: &str,
+    ) -> Result<bool> {
+        let prefix = format!("{id}.");
+        let mut files: Vec<_> = fs::read_dir("songs")?
+            .filter_map(|x| x.ok())
+            .map(|x| x.path())
+            .filter(|x| {
+                let name = x.file_name().and_then(|x| x.to_str()).unwrap_or_default();
+                name.starts_with(&prefix) && name.ends_with(".lrc")
+            })
+            .collect();
+        files.sort();
+        let lyrics = files.first().and_then(|x| fs::read_to_string(x).ok());
+        for file in &files {
+            let _ = fs::remove_file(file);
+        }
+        let Some(lyrics) = lyrics else {
+            return Ok(false);
+        };
+        if Self::for_song(db, song).await?.is_some() {
+            return Ok(false);
+        }
+        Self::from_text(song, &lyrics, "subtitles").save(db).await?;
+        Ok(true)
+    }
+}
+
+// plain lyrics of a song kept in the search index
+pub struct SearchableLyrics {
+    pub song: String,
+    pub plain: String,
+}
+
+impl SearchableLyrics {
+    pub async fn load(db: &mut PoolConnection<Postgres>) -> Result<Vec<Self>> {
+        Ok(query_as!(
+            SearchableLyrics,
+            "select song, plain from lyrics where plain <> ''"
+        )
+        .fetch_all(db)
+        .await?)
+    }
+}
+
+impl<'a> FuzzyComparable<'a> for SearchableLyrics {
+    fn search_term(&self, _: &SearchType) -> &str {
+        &self.plain
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(time: f64, text: &str) -> LrcLine {
+        LrcLine {
+            time,
+            text: text.to_string(),
+        }
+    }
+
+    fn assert_lines(lrc: &str, expected: &[LrcLine]) {
+        let lines = parse_lrc(lrc);
+        assert_eq!(lines.len(), expected.len(), "{lines:?}");
+        for (line, expected) in lines.iter().zip(expected) {
+            assert!((line.time - expected.time).abs() < 1e-9, "{line:?}");
+            assert_eq!(line.text, expected.text);
+        }
+    }
+
+    #[test]
+    fn timestamp_forms() {
+        assert_eq!(parse_timestamp("01:02.50"), Some(62.5));
+        assert_eq!(parse_timestamp("01:02"), Some(62.0));
+        assert_eq!(parse_timestamp("01:02:50"), Some(62.5));
+        assert_eq!(parse_timestamp("00:00.000"), Some(0.0));
+        assert_eq!(parse_timestamp("ar:Artist"), None);
+        assert_eq!(parse_timestamp("1:xx"), None);
+        assert_eq!(parse_timestamp("0102"), None);
+    }
+
+    #[test]
+    fn lines() {
+        let lrc = "[00:12.00]first\n[00:15]second\n  [00:17.5]  padded  \n[00:20.00]\n";
+        assert_lines(
+            lrc,
+            &[
+                line(12.0, "first"),
+                line(15.0, "second"),
+                line(17.5, "padded"),
+                line(20.0, ""),
+            ],
+        );
+    }
+
+    #[test]
+    fn repeated_lines() {
+        // one line with several timestamps shows up at each of them, in time order
+        let lrc = "[00:30.00][00:10.00]chorus\n[00:20.00]verse\n[01:00][00:40]chorus again";
+        assert_lines(
+            lrc,
+            &[
+                line(10.0, "chorus"),
+                line(20.0, "verse"),
+                line(30.0, "chorus"),
+                line(40.0, "chorus again"),
+                line(60.0, "chorus again"),
+            ],
+        );
+    }
+
+    #[test]
+    fn metadata_and_offset() {
+        let lrc = "[ar:Artist]\n[ti:Title]\n[length: 03:20]\n[offset:+500]\n\
+                   [00:00.20]starts right away\n[00:10.00]later\nplain text is ignored";
+        assert_lines(lrc, &[line(0.0, "starts right away"), line(9.5, "later")]);
+        // a negative offset shows the lyrics later
+        assert_lines("[offset:-1500]\n[00:01.00]one", &[line(2.5, "one")]);
+        // a broken offset is ignored
+        assert_lines("[offset:soon]\n[00:01.00]one", &[line(1.0, "one")]);
+    }
+
+    #[test]
+    fn plain_text() {
+        assert!(parse_lrc("just some lyrics\n[chorus]\nmore").is_empty());
+        let lyrics = Lyrics::from_text("id", "  just some lyrics\n", "upload");
+        assert_eq!(lyrics.plain, "just some lyrics");
+        assert_eq!(lyrics.synced, None);
+        let lyrics = Lyrics::from_text("id", "[00:01]one\n[00:02]\n[00:03]two", "upload");
+        assert_eq!(lyrics.plain, "one\ntwo");
+        assert!(lyrics.synced.is_some());
+    }
+}
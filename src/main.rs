@@ -1,26 +1,48 @@
 mod api;
+mod audio;
+mod catalog;
+mod chapters;
+mod covers;
+mod downloader;
+mod events;
 mod extractors;
 mod fuzzy;
+mod library;
+mod loudness;
+mod lyrics;
+mod media;
 mod middlewares;
+mod quota;
+mod reconcile;
+mod signing;
+mod titles;
+mod transcode;
 mod types;
+mod waveform;
 mod youtube;
 
 use actix_cors::Cors;
 use actix_web::{App, HttpServer, Scope};
 use dotenv::dotenv;
 
-use crate::types::{Config, DownloadCache, SongSearch};
+use crate::events::Sessions;
+use crate::media::Extractor;
+use crate::types::{Config, SongSearch};
 use actix::{Actor, StreamHandler};
-use actix_files::Files;
 use actix_web::{get, web, Error as ActixError, HttpRequest, HttpResponse};
 use actix_web_actors::ws;
 use async_once::AsyncOnce;
 use lazy_static::lazy_static;
 use log::{error, info};
 use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
-use std::{error::Error, time::Duration};
-use tokio::sync::RwLock;
+use std::{
+    error::Error,
+    time::{Duration, SystemTime},
+};
+use tokio::sync::{Notify, RwLock};
 
 pub(crate) const VERSION: &str = "0.1.0";
 pub(crate) const BRANCH: &str = "main";
@@ -39,8 +61,22 @@ lazy_static! {
             SongSearch::load(&mut (DB.get().await).db.try_acquire().unwrap()).await,
         ))
     });
-    pub(crate) static ref DOWNLOAD_CACHE: Arc<Mutex<DownloadCache>> =
-        Arc::new(Mutex::new(DownloadCache::default()));
+    pub(crate) static ref DOWNLOAD_NOTIFY: Notify = Notify::new();
+    pub(crate) static ref EXTRACTOR: Box<dyn Extractor> = media::from_config();
+    pub(crate) static ref SESSIONS: Arc<Mutex<Sessions>> = Arc::new(Mutex::new(Sessions::default()));
+    // signs media urls, with a random key the urls stop working when the server restarts
+    pub(crate) static ref SIGNING_KEY: Vec<u8> = if CONFIG.media_signing_key.is_empty() {
+        rand::random::<[u8; 32]>().to_vec()
+    } else {
+        CONFIG.media_signing_key.as_bytes().to_vec()
+    };
+    // variants being transcoded right now, every request for one waits on the same encode
+    pub(crate) static ref TRANSCODES: Mutex<HashMap<String, transcode::Encoding>> =
+        Mutex::new(HashMap::new());
+    // when each cached variant was last served, the cache evicts by this and not by the file
+    // times because those are what the etag and last-modified of a variant come from
+    pub(crate) static ref TRANSCODES_USED: Mutex<HashMap<PathBuf, SystemTime>> =
+        Mutex::new(HashMap::new());
 }
 
 struct Database {
@@ -90,6 +126,7 @@ fn ws_routes() -> Scope {
         .service(next_song)
         .service(previous_song)
         .service(play_pause)
+        .service(events::events)
 }
 
 #[actix_web::main]
@@ -97,6 +134,26 @@ async fn main() -> std::io::Result<()> {
     dotenv().ok();
     pretty_env_logger::init();
 
+    // seanify-backendv2 reconcile [--dry-run]
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("reconcile") {
+        let dry_run = args.iter().any(|x| x == "--dry-run");
+        let mut db = DB
+            .get()
+            .await
+            .db
+            .acquire()
+            .await
+            .map_err(std::io::Error::other)?;
+        let report = reconcile::run(&mut db, reconcile::CLI_USER, dry_run)
+            .await
+            .map_err(std::io::Error::other)?;
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    actix_web::rt::spawn(downloader::supervise());
+
     HttpServer::new(move || {
         let auth0_config = extractors::Auth0Config::default();
         let cors = Cors::permissive();
@@ -111,9 +168,8 @@ async fn main() -> std::io::Result<()> {
             .service(api::users::routes())
             .service(api::playlist::routes())
             .service(api::songs::routes())
-            .service(Files::new("./profiles", "."))
-            .service(Files::new("./playlist", "."))
-            .service(Files::new("./songs", "."))
+            .service(api::catalog::routes())
+            .service(api::signing::routes())
     })
     .bind((&*CONFIG.host, CONFIG.port))?
     .run()
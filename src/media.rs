@@ -0,0 +1,63 @@
+mod fake;
+mod ytdlp;
+
+pub use self::fake::FakeExtractor;
+pub use self::ytdlp::YtDlp;
+
+use crate::{
+    youtube::{PlaylistInfo, Progress},
+    CONFIG,
+};
+use derive_more::Display;
+use std::{future::Future, io, pin::Pin};
+
+pub type ProgressFn = Box<dyn Fn(Progress) + Send + Sync>;
+pub type ExtractorFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, ExtractorError>> + 'a>>;
+
+#[derive(Debug, Display)]
+pub enum ExtractorError {
+    #[display(fmt = "{} is not installed", _0)]
+    NotInstalled(String),
+    #[display(fmt = "extraction timed out after {} seconds", _0)]
+    Timeout(u64),
+    #[display(fmt = "extractor exited with {}", _0)]
+    Failed(String),
+    #[display(fmt = "missing fixture {}", _0)]
+    MissingFixture(String),
+    #[display(fmt = "could not read extractor output: {}", _0)]
+    InvalidOutput(serde_json::Error),
+    #[display(fmt = "io error: {}", _0)]
+    Io(io::Error),
+}
+
+impl std::error::Error for ExtractorError {}
+
+impl From<io::Error> for ExtractorError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+// something that can fetch audio for a source id, implementations have to leave
+// songs/{id}.mp3 and songs/{id}.info.json behind when they succeed
+pub trait Extractor: Send + Sync {
+    fn extract<'a>(
+        &'a self,
+        id: &'a str,
+        url: &'a str,
+        progress: ProgressFn,
+    ) -> ExtractorFuture<'a, ()>;
+
+    // only write songs/{id}.info.json, used to refresh the metadata of a song
+    fn metadata<'a>(&'a self, id: &'a str, url: &'a str) -> ExtractorFuture<'a, ()>;
+
+    // list the videos of a playlist or channel without downloading any of them
+    fn expand<'a>(&'a self, id: &'a str, url: &'a str) -> ExtractorFuture<'a, PlaylistInfo>;
+}
+
+pub fn from_config() -> Box<dyn Extractor> {
+    match CONFIG.extractor.as_str() {
+        "fake" => Box::new(FakeExtractor::new(&CONFIG.fixtures_dir)),
+        _ => Box::new(YtDlp::new(CONFIG.extractor_timeout_sec)),
+    }
+}
@@ -0,0 +1,179 @@
+use super::{Extractor, ExtractorError, ExtractorFuture, ProgressFn};
+use crate::youtube::{PlaylistInfo, Progress};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+// copies pre downloaded files out of a fixture directory so ingest can run without network
+// access, the fixture directory needs {id}.mp3 and {id}.info.json for every id that is requested
+// and {id}.playlist.json in the yt-dlp flat playlist format for every playlist, an optional
+// {id}.lrc is handed over like a converted subtitle
+pub struct FakeExtractor {
+    fixtures: PathBuf,
+}
+
+impl FakeExtractor {
+    pub fn new(fixtures: impl AsRef<Path>) -> Self {
+        Self {
+            fixtures: fixtures.as_ref().to_path_buf(),
+        }
+    }
+
+    async fn copy(&self, file: &str) -> Result<(), ExtractorError> {
+        let from = self.fixtures.join(file);
+        if !from.exists() {
+            return Err(ExtractorError::MissingFixture(from.display().to_string()));
+        }
+        fs::copy(from, format!("songs/{file}")).await?;
+        Ok(())
+    }
+}
+
+impl Extractor for FakeExtractor {
+    fn extract<'a>(
+        &'a self,
+        id: &'a str,
+        _url: &'a str,
+        progress: ProgressFn,
+    ) -> ExtractorFuture<'a, ()> {
+        Box::pin(async move {
+            fs::create_dir_all("songs").await?;
+            self.copy(&format!("{id}.mp3")).await?;
+            self.copy(&format!("{id}.info.json")).await?;
+            let lrc = self.fixtures.join(format!("{id}.lrc"));
+            if lrc.exists() {
+                fs::copy(lrc, format!("songs/{id}.en.lrc")).await?;
+            }
+            progress(Progress {
+                percent: 100.0,
+                speed: None,
+                eta: Some(0),
+            });
+            Ok(())
+        })
+    }
+
+    fn metadata<'a>(&'a self, id: &'a str, _url: &'a str) -> ExtractorFuture<'a, ()> {
+        Box::pin(async move {
+            fs::create_dir_all("songs").await?;
+            self.copy(&format!("{id}.info.json")).await
+        })
+    }
+
+    fn expand<'a>(&'a self, id: &'a str, _url: &'a str) -> ExtractorFuture<'a, PlaylistInfo> {
+        Box::pin(async move {
+            let path = self.fixtures.join(format!("{id}.playlist.json"));
+            if !path.exists() {
+                return Err(ExtractorError::MissingFixture(path.display().to_string()));
+            }
+            let data = fs::read(path).await?;
+            serde_json::from_slice(&data).map_err(ExtractorError::InvalidOutput)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Song;
+    use sqlx::{query_as, PgPool};
+    use std::sync::{Arc, Mutex};
+
+    const ID: &str = "fakeingest1";
+
+    // about a second of silent 128 kbps 44.1 kHz mpeg-1 layer iii frames followed by an id3v1 tag
+    fn silent_mp3() -> Vec<u8> {
+        let mut frame = vec![0u8; 417];
+        frame[..4].copy_from_slice(&[0xff, 0xfb, 0x90, 0xc0]);
+        let mut data = frame.repeat(39);
+        let mut tag = vec![0u8; 128];
+        let mut field =
+            |at: usize, text: &str| tag[at..at + text.len()].copy_from_slice(text.as_bytes());
+        field(0, "TAG");
+        field(3, "Fake Song");
+        field(33, "Fake Artist");
+        field(63, "Fake Album");
+        tag[127] = 255;
+        data.extend(tag);
+        data
+    }
+
+    fn info_json() -> String {
+        serde_json::json!({
+            "id": ID,
+            "title": "Fake Artist - Fake Song (Official Audio)",
+            "thumbnail": "https://example.com/thumb.jpg",
+            "uploader": "Fake Artist - Topic",
+            "uploader_url": "https://example.com/channel",
+            "duration": 1,
+            "age_limit": 0,
+            "webpage_url": format!("https://www.youtube.com/watch?v={ID}"),
+            "was_live": false,
+            "upload_date": "20240101",
+            "filesize": 20000,
+            "chapters": null,
+        })
+        .to_string()
+    }
+
+    #[actix_web::test]
+    async fn ingest() {
+        let fixtures = std::env::temp_dir().join("seanify-fake-extractor");
+        std::fs::create_dir_all(&fixtures).unwrap();
+        std::fs::write(fixtures.join(format!("{ID}.mp3")), silent_mp3()).unwrap();
+        std::fs::write(fixtures.join(format!("{ID}.info.json")), info_json()).unwrap();
+        let pool = PgPool::connect(env!("DATABASE_URL"))
+            .await
+            .expect("the tests need the database the queries were checked against");
+        let mut db = pool.acquire().await.unwrap();
+        Song::delete(&mut db, &[ID.to_string()]).await.unwrap();
+
+        let extractor = FakeExtractor::new(&fixtures);
+        let done = Arc::new(Mutex::new(0.0));
+        let seen = done.clone();
+        let progress: ProgressFn = Box::new(move |x| *seen.lock().unwrap() = x.percent);
+        extractor.extract(ID, "", progress).await.unwrap();
+        assert_eq!(*done.lock().unwrap(), 100.0);
+        assert!(extractor
+            .extract("missing", "", Box::new(|_| ()))
+            .await
+            .is_err());
+
+        let path = format!("songs/{ID}.mp3");
+        let (hash, existing) = Song::find_by_audio(&mut db, path.clone()).await;
+        assert!(hash.is_some());
+        assert!(existing.is_none());
+        let url = format!("https://www.youtube.com/watch?v={ID}");
+        Song::insert(
+            ID.to_string(),
+            &mut db,
+            &url,
+            "tester".to_string(),
+            hash.clone(),
+        )
+        .await
+        .unwrap();
+
+        let song = query_as!(Song, "select * from songs where id = $1", ID)
+            .fetch_one(&mut db)
+            .await
+            .unwrap();
+        assert_eq!(song.title, "Fake Song");
+        assert_eq!(song.artist, "Fake Artist");
+        assert_eq!(song.album, "Fake Album");
+        assert_eq!(song.uploader, "Fake Artist");
+        assert_eq!(song.raw_title, "Fake Artist - Fake Song (Official Audio)");
+        assert_eq!(song.url, url);
+        assert_eq!(song.added_by, "tester");
+        assert_eq!(song.format, "mp3");
+        assert_eq!(song.audio_hash, hash);
+        assert!((song.duration - 1.0).abs() < 0.1, "{}", song.duration);
+
+        // the same audio again is found instead of ingested twice
+        let (_, existing) = Song::find_by_audio(&mut db, path).await;
+        assert_eq!(existing.map(|x| x.id).as_deref(), Some(ID));
+
+        Song::delete(&mut db, &[ID.to_string()]).await.unwrap();
+        assert!(!Path::new(&format!("songs/{ID}.mp3")).exists());
+        let _ = std::fs::remove_dir_all(fixtures);
+    }
+}
@@ -0,0 +1,144 @@
+use super::{Extractor, ExtractorError, ExtractorFuture, ProgressFn};
+use crate::{
+    youtube::{PlaylistInfo, Progress},
+    CONFIG,
+};
+use std::{io::ErrorKind, process::Stdio, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::Command,
+    time::timeout,
+};
+
+const BINARY: &str = "yt-dlp";
+
+pub struct YtDlp {
+    // hard limit for a single download, the process is killed once it runs over
+    timeout: u64,
+}
+
+impl YtDlp {
+    pub fn new(timeout: u64) -> Self {
+        Self { timeout }
+    }
+
+    pub(crate) fn command() -> Command {
+        let mut cmd = Command::new(BINARY);
+        cmd.kill_on_drop(true);
+        cmd
+    }
+
+    pub(crate) fn spawn_error(e: std::io::Error) -> ExtractorError {
+        if e.kind() == ErrorKind::NotFound {
+            ExtractorError::NotInstalled(BINARY.to_string())
+        } else {
+            ExtractorError::Io(e)
+        }
+    }
+}
+
+// yt-dlp --socket-timeout 3 --embed-thumbnail --audio-format mp3 --extract-audio --output "M3HhNcl2dMA.%(ext)s" --add-metadata --write-info-json https://www.youtube.com/watch\?v\=M3HhNcl2dMA
+impl Extractor for YtDlp {
+    fn extract<'a>(
+        &'a self,
+        id: &'a str,
+        url: &'a str,
+        progress: ProgressFn,
+    ) -> ExtractorFuture<'a, ()> {
+        Box::pin(async move {
+            let mut cmd = Self::command();
+            cmd.args([
+                "--socket-timeout",
+                &CONFIG.yt_timeout_sec,
+                "--embed-thumbnail",
+                "--audio-format",
+                "mp3",
+                "--retries",
+                &CONFIG.retries.to_string(),
+                "--extract-audio",
+                // a redownload has to replace the file that is already there
+                "--force-overwrites",
+                "--add-metadata",
+                "--output",
+                &format!("songs/{id}.%(ext)s"),
+                "--write-info-json",
+                // subtitles become lrc lyrics, see Lyrics::import_subtitles
+                "--write-subs",
+                "--sub-langs",
+                &CONFIG.subtitle_langs,
+                "--convert-subs",
+                "lrc",
+                "--newline",
+                url,
+            ])
+            .stdout(Stdio::piped());
+            let mut child = cmd.spawn().map_err(Self::spawn_error)?;
+            let stdout = child.stdout.take();
+            let run = async {
+                if let Some(stdout) = stdout {
+                    let mut lines = BufReader::new(stdout).lines();
+                    while let Some(line) = lines.next_line().await? {
+                        if let Some(v) = Progress::parse(&line) {
+                            progress(v);
+                        }
+                    }
+                }
+                child.wait().await
+            };
+            match timeout(Duration::from_secs(self.timeout), run).await {
+                Ok(Ok(status)) if status.success() => Ok(()),
+                Ok(Ok(status)) => Err(ExtractorError::Failed(status.to_string())),
+                Ok(Err(e)) => Err(ExtractorError::Io(e)),
+                Err(_) => {
+                    let _ = child.kill().await;
+                    Err(ExtractorError::Timeout(self.timeout))
+                }
+            }
+        })
+    }
+
+    fn metadata<'a>(&'a self, id: &'a str, url: &'a str) -> ExtractorFuture<'a, ()> {
+        Box::pin(async move {
+            let mut cmd = Self::command();
+            cmd.args([
+                "--socket-timeout",
+                &CONFIG.yt_timeout_sec,
+                "--skip-download",
+                "--write-info-json",
+                "--output",
+                &format!("songs/{id}.%(ext)s"),
+                url,
+            ]);
+            let output = match timeout(Duration::from_secs(self.timeout), cmd.output()).await {
+                Ok(v) => v.map_err(Self::spawn_error)?,
+                Err(_) => return Err(ExtractorError::Timeout(self.timeout)),
+            };
+            if !output.status.success() {
+                return Err(ExtractorError::Failed(output.status.to_string()));
+            }
+            Ok(())
+        })
+    }
+
+    fn expand<'a>(&'a self, _id: &'a str, url: &'a str) -> ExtractorFuture<'a, PlaylistInfo> {
+        Box::pin(async move {
+            let mut cmd = Self::command();
+            cmd.args([
+                "--socket-timeout",
+                &CONFIG.yt_timeout_sec,
+                "--flat-playlist",
+                "--dump-single-json",
+                url,
+            ]);
+            let output = match timeout(Duration::from_secs(self.timeout), cmd.output()).await {
+                Ok(v) => v.map_err(Self::spawn_error)?,
+                // dropping the future kills the process
+                Err(_) => return Err(ExtractorError::Timeout(self.timeout)),
+            };
+            if !output.status.success() {
+                return Err(ExtractorError::Failed(output.status.to_string()));
+            }
+            serde_json::from_slice(&output.stdout).map_err(ExtractorError::InvalidOutput)
+        })
+    }
+}
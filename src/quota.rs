@@ -0,0 +1,189 @@
+use crate::{types::User, CONFIG};
+use anyhow::Result;
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use sqlx::{pool::PoolConnection, query, query_as, Postgres};
+
+const MB: i64 = 1024 * 1024;
+
+#[derive(Debug, Display)]
+pub enum QuotaError {
+    #[display(fmt = "you can add at most {} songs", _0)]
+    Songs(i64),
+    #[display(fmt = "you can store at most {} MB of songs", _0)]
+    Storage(i64),
+    #[display(fmt = "you can queue at most {} downloads per hour", _0)]
+    RateLimited(i64),
+    #[display(fmt = "could not check quota: {}", _0)]
+    Unavailable(String),
+}
+
+impl std::error::Error for QuotaError {}
+
+// limits for a single user, a row in user_quotas overrides the server defaults
+#[derive(Serialize, Deserialize)]
+pub struct Quota {
+    pub max_songs: Option<i64>,
+    pub max_bytes: Option<i64>,
+    pub jobs_per_hour: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct QuotaUsage {
+    pub songs: i64,
+    pub bytes: i64,
+    pub jobs_last_hour: i64,
+    pub max_songs: i64,
+    pub max_bytes: i64,
+    pub jobs_per_hour: i64,
+    pub exempt: bool,
+}
+
+impl Quota {
+    pub async fn for_user(db: &mut PoolConnection<Postgres>, id: &str) -> Result<Self> {
+        let quota = query_as!(
+            Quota,
+            "select max_songs, max_bytes, jobs_per_hour from user_quotas where id = $1",
+            id
+        )
+        .fetch_optional(db)
+        .await?;
+        Ok(quota.unwrap_or(Quota {
+            max_songs: None,
+            max_bytes: None,
+            jobs_per_hour: None,
+        }))
+    }
+
+    pub async fn set(&self, db: &mut PoolConnection<Postgres>, id: &str) -> Result<()> {
+        query!(
+            r#"insert into user_quotas(id, max_songs, max_bytes, jobs_per_hour)
+            values($1, $2, $3, $4)
+            on conflict (id) do update set
+                max_songs = excluded.max_songs,
+                max_bytes = excluded.max_bytes,
+                jobs_per_hour = excluded.jobs_per_hour"#,
+            id,
+            self.max_songs,
+            self.max_bytes,
+            self.jobs_per_hour
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn usage(db: &mut PoolConnection<Postgres>, user: &User) -> Result<QuotaUsage> {
+        let quota = Self::for_user(db, &user.id).await?;
+        let songs = query!(
+            r#"select count(*) as "count!", coalesce(sum(filesize), 0)::bigint as "bytes!"
+            from songs where added_by = $1"#,
+            user.id
+        )
+        .fetch_one(&mut *db)
+        .await?;
+        let jobs_last_hour = query!(
+            r#"select count(*) as "count!" from download_jobs
+            where requester = $1 and created > extract(epoch from now())::bigint - 3600"#,
+            user.id
+        )
+        .fetch_one(&mut *db)
+        .await?
+        .count;
+        Ok(QuotaUsage {
+            songs: songs.count,
+            bytes: songs.bytes,
+            jobs_last_hour,
+            max_songs: quota.max_songs.unwrap_or(CONFIG.user_max_songs as i64),
+            max_bytes: quota
+                .max_bytes
+                .unwrap_or(CONFIG.user_max_storage_mb as i64 * MB),
+            jobs_per_hour: quota
+                .jobs_per_hour
+                .unwrap_or(CONFIG.user_jobs_per_hour as i64),
+            exempt: user.admin,
+        })
+    }
+
+    // whether the user can add `songs` more songs, admins are never limited, `queueing` also
+    // applies the hourly download job limit
+    pub async fn check(
+        db: &mut PoolConnection<Postgres>,
+        user: &User,
+        songs: usize,
+        queueing: bool,
+    ) -> Result<(), QuotaError> {
+        if user.admin {
+            return Ok(());
+        }
+        Self::usage(db, user)
+            .await
+            .map_err(|e| QuotaError::Unavailable(e.to_string()))?
+            .allows(songs, queueing)
+    }
+}
+
+impl QuotaUsage {
+    // every one of `songs` is a download job when `queueing`, so a batch counts against the
+    // hourly limit as a whole
+    fn allows(&self, songs: usize, queueing: bool) -> Result<(), QuotaError> {
+        if self.songs + songs as i64 > self.max_songs {
+            return Err(QuotaError::Songs(self.max_songs));
+        }
+        if self.bytes >= self.max_bytes {
+            return Err(QuotaError::Storage(self.max_bytes / MB));
+        }
+        if queueing && self.jobs_last_hour + songs as i64 > self.jobs_per_hour {
+            return Err(QuotaError::RateLimited(self.jobs_per_hour));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(songs: i64, jobs_last_hour: i64) -> QuotaUsage {
+        QuotaUsage {
+            songs,
+            bytes: 0,
+            jobs_last_hour,
+            max_songs: 500,
+            max_bytes: 2048 * MB,
+            jobs_per_hour: 30,
+            exempt: false,
+        }
+    }
+
+    #[test]
+    fn batch_crosses_hourly_limit() {
+        assert!(usage(0, 0).allows(30, true).is_ok());
+        assert!(matches!(
+            usage(0, 0).allows(31, true),
+            Err(QuotaError::RateLimited(30))
+        ));
+        assert!(matches!(
+            usage(0, 25).allows(6, true),
+            Err(QuotaError::RateLimited(30))
+        ));
+        assert!(usage(0, 29).allows(1, true).is_ok());
+        // a split or an upload queues nothing
+        assert!(usage(0, 30).allows(6, false).is_ok());
+    }
+
+    #[test]
+    fn songs_and_storage() {
+        assert!(usage(499, 0).allows(1, false).is_ok());
+        assert!(matches!(
+            usage(499, 0).allows(2, false),
+            Err(QuotaError::Songs(500))
+        ));
+        let mut full = usage(0, 0);
+        full.bytes = full.max_bytes;
+        assert!(matches!(
+            full.allows(1, false),
+            Err(QuotaError::Storage(2048))
+        ));
+    }
+}
@@ -0,0 +1,157 @@
+use crate::{library, types::Song, youtube::VideoData, SONG_SEARCH};
+use actix_web::web;
+use anyhow::Result;
+use log::info;
+use serde::Serialize;
+use sqlx::{pool::PoolConnection, query, Postgres};
+use std::{collections::HashMap, fs, path::Path};
+
+const AUDIO_FORMATS: [&str; 4] = ["mp3", "flac", "ogg", "m4a"];
+// who imported songs are attributed to when the scan is started from the command line
+pub const CLI_USER: &str = "reconcile";
+
+#[derive(Serialize)]
+pub struct Skipped {
+    pub id: String,
+    pub reason: String,
+}
+
+// everything the scan found, in a dry run the lists are what would have been changed
+#[derive(Serialize, Default)]
+pub struct Report {
+    pub dry_run: bool,
+    // audio files without a row that were imported
+    pub imported: Vec<String>,
+    // audio files without a row that could not be imported
+    pub skipped: Vec<Skipped>,
+    // rows whose audio file is gone, they are flagged as missing
+    pub missing: Vec<String>,
+    // rows flagged as missing whose file is back
+    pub restored: Vec<String>,
+    pub orphan_bytes: u64,
+    // sum of the recorded file sizes of the missing rows
+    pub missing_bytes: i64,
+    pub folder_bytes: u64,
+}
+
+struct AudioFile {
+    format: String,
+    size: u64,
+}
+
+// audio files in songs/ by id, covers, lyrics and info files are left out
+fn scan() -> Result<HashMap<String, AudioFile>> {
+    let mut files = HashMap::new();
+    if !Path::new("songs").exists() {
+        return Ok(files);
+    }
+    for entry in fs::read_dir("songs")? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Some((id, format)) = name.split_once('.') else {
+            continue;
+        };
+        if metadata.is_file() && AUDIO_FORMATS.contains(&format) {
+            files.insert(
+                id.to_string(),
+                AudioFile {
+                    format: format.to_string(),
+                    size: metadata.len(),
+                },
+            );
+        }
+    }
+    Ok(files)
+}
+
+pub async fn run(db: &mut PoolConnection<Postgres>, user: &str, dry_run: bool) -> Result<Report> {
+    let mut report = Report {
+        dry_run,
+        ..Default::default()
+    };
+    let mut files = web::block(scan).await??;
+    let rows = query!("select id, format, filesize, missing from songs")
+        .fetch_all(&mut *db)
+        .await?;
+    for row in rows {
+        let exists = match files.remove(&row.id) {
+            Some(file) => file.format == row.format,
+            None => false,
+        };
+        if exists != row.missing {
+            continue;
+        }
+        if exists {
+            info!("{} is back in songs/", row.id);
+            report.restored.push(row.id.clone());
+        } else {
+            info!("{}.{} is missing", row.id, row.format);
+            report.missing_bytes += row.filesize;
+            report.missing.push(row.id.clone());
+        }
+        if !dry_run {
+            query!(
+                "update songs set missing = $1 where id = $2",
+                !exists,
+                row.id
+            )
+            .execute(&mut *db)
+            .await?;
+        }
+    }
+    // whatever is left has no row
+    for (id, file) in files {
+        report.orphan_bytes += file.size;
+        let skip = if file.format != "mp3" {
+            Some(format!(
+                "{} files can only be added by uploading them",
+                file.format
+            ))
+        } else if !Path::new(&format!("songs/{id}.info.json")).exists() {
+            Some(format!("no {id}.info.json to take the metadata from"))
+        } else {
+            None
+        };
+        if let Some(reason) = skip {
+            info!("skipping {id}: {reason}");
+            report.skipped.push(Skipped { id, reason });
+            continue;
+        }
+        info!("importing {id}");
+        if dry_run {
+            report.imported.push(id);
+            continue;
+        }
+        match import(db, &id, user).await {
+            Ok(()) => report.imported.push(id),
+            Err(reason) => report.skipped.push(Skipped { id, reason }),
+        }
+    }
+    report.folder_bytes = web::block(|| library::folder_size("songs")).await??;
+    let changed =
+        !(report.imported.is_empty() && report.missing.is_empty() && report.restored.is_empty());
+    if !dry_run && changed {
+        SONG_SEARCH.get().await.write().await.update(db).await;
+    }
+    Ok(report)
+}
+
+async fn import(db: &mut PoolConnection<Postgres>, id: &str, user: &str) -> Result<(), String> {
+    let data = VideoData::load_and_replace(id).map_err(|e| e.to_string())?;
+    let (audio_hash, existing) = Song::find_by_audio(db, format!("songs/{id}.mp3")).await;
+    if let Some(existing) = existing {
+        return Err(format!("same audio as {}", existing.id));
+    }
+    let mut song = Song::insert(
+        id.to_string(),
+        db,
+        &data.webpage_url,
+        user.to_string(),
+        audio_hash,
+    )
+    .await
+    .map_err(|e| e.to_string())?;
+    song.analyze(db).await;
+    Ok(())
+}
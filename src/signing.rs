@@ -0,0 +1,146 @@
+use crate::{extractors::Claims, CONFIG, SIGNING_KEY};
+use derive_more::Display;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::form_urlencoded::byte_serialize;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// what a signed url can point at, players and cast targets can not send a bearer token
+#[derive(Debug, Display, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Media {
+    #[display(fmt = "stream")]
+    Stream,
+    #[display(fmt = "cover")]
+    Cover,
+    #[display(fmt = "pfp")]
+    Pfp,
+    #[display(fmt = "playlist_cover")]
+    PlaylistCover,
+}
+
+impl Media {
+    fn path(&self, id: &str) -> String {
+        let id: String = byte_serialize(id.as_bytes()).collect();
+        match self {
+            Self::Stream => format!("/songs/{id}/stream"),
+            Self::Cover => format!("/songs/{id}/cover"),
+            Self::Pfp => format!("/users/pfp/{id}"),
+            Self::PlaylistCover => format!("/playlist/cover/{id}"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct SignedUrl {
+    pub url: String,
+    pub expires: i64,
+}
+
+// the query of a signed url, every field is missing on requests that use a bearer token
+#[derive(Deserialize)]
+pub struct Signature {
+    user: Option<String>,
+    expires: Option<i64>,
+    sig: Option<String>,
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+fn mac(media: Media, id: &str, user: &str, expires: i64) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(&SIGNING_KEY).expect("hmac accepts keys of any length");
+    mac.update(format!("{media}\n{id}\n{user}\n{expires}").as_bytes());
+    mac
+}
+
+// a url for `id` that works without a bearer token for CONFIG.media_url_ttl_sec seconds and
+// acts as `user` while it does, a stream url lasts `playing` seconds longer so a player can
+// keep sending range requests until the song is over
+pub fn sign(media: Media, id: &str, user: &str, playing: f64) -> SignedUrl {
+    let expires = now() + CONFIG.media_url_ttl_sec as i64 + playing.max(0.0).ceil() as i64;
+    let sig = hex::encode(mac(media, id, user, expires).finalize().into_bytes());
+    let user: String = byte_serialize(user.as_bytes()).collect();
+    SignedUrl {
+        url: format!("{}?user={user}&expires={expires}&sig={sig}", media.path(id)),
+        expires,
+    }
+}
+
+impl Signature {
+    // the user the url was signed for when the signature matches and has not expired
+    pub fn user(&self, media: Media, id: &str) -> Option<&str> {
+        let (Some(user), Some(expires), Some(sig)) = (&self.user, self.expires, &self.sig) else {
+            return None;
+        };
+        if expires < now() {
+            return None;
+        }
+        let sig = hex::decode(sig).ok()?;
+        mac(media, id, user, expires)
+            .verify_slice(&sig)
+            .ok()
+            .map(|_| user.as_str())
+    }
+}
+
+// who is asking for a media route, a bearer token wins over a signature
+pub fn caller(
+    claims: Option<Claims>,
+    signature: &Signature,
+    media: Media,
+    id: &str,
+) -> Option<String> {
+    match claims {
+        Some(claims) => Some(claims.sub),
+        None => signature.user(media, id).map(str::to_string),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signature(url: &SignedUrl) -> Signature {
+        let query = url.url.split_once('?').unwrap().1;
+        let field = |name: &str| {
+            url::form_urlencoded::parse(query.as_bytes())
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.to_string())
+        };
+        Signature {
+            user: field("user"),
+            expires: field("expires").and_then(|x| x.parse().ok()),
+            sig: field("sig"),
+        }
+    }
+
+    #[test]
+    fn stream_lasts_the_song() {
+        let cover = sign(Media::Cover, "a song", "someone", 0.0);
+        let stream = sign(Media::Stream, "a song", "someone", 241.3);
+        assert!((242..=243).contains(&(stream.expires - cover.expires)));
+        assert_eq!(
+            signature(&stream).user(Media::Stream, "a song"),
+            Some("someone")
+        );
+        assert_eq!(signature(&stream).user(Media::Cover, "a song"), None);
+        assert_eq!(signature(&stream).user(Media::Stream, "other"), None);
+    }
+
+    #[test]
+    fn expired() {
+        let mut url = signature(&sign(Media::Stream, "a song", "someone", 0.0));
+        assert!(url.user(Media::Stream, "a song").is_some());
+        url.expires = Some(now() - 1);
+        assert_eq!(url.user(Media::Stream, "a song"), None);
+    }
+}
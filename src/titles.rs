@@ -0,0 +1,268 @@
+use crate::CONFIG;
+
+// words that mark a bracketed part of a title as noise, "(Official Audio)" or "[Lyrics]"
+const NOISE: [&str; 14] = [
+    "official",
+    "audio",
+    "video",
+    "lyrics",
+    "lyric",
+    "visualizer",
+    "visualiser",
+    "hd",
+    "hq",
+    "4k",
+    "mv",
+    "m/v",
+    "clip",
+    "full song",
+];
+// words dropped from the end of a title even when they are not in brackets
+const TRAILING_NOISE: [&str; 4] = ["hd", "hq", "4k", "lyrics"];
+const SEPARATORS: [&str; 3] = [" - ", " – ", " — "];
+const BRACKETS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('【', '】')];
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Cleaned {
+    pub title: String,
+    pub artist: String,
+}
+
+// clean a video title, `artist` is the artist from the tags if there is one, otherwise the
+// artist is taken from an "Artist - Title" title or the uploader
+pub fn clean(title: &str, artist: &str, uploader: &str) -> Cleaned {
+    let raw = Cleaned {
+        title: title.to_string(),
+        artist: if artist.is_empty() {
+            uploader.to_string()
+        } else {
+            artist.to_string()
+        },
+    };
+    if !CONFIG.clean_titles {
+        return raw;
+    }
+    let uploader = clean_uploader(uploader);
+    let title = strip_noise(title, &CONFIG.title_noise);
+    let split = SEPARATORS
+        .iter()
+        .find_map(|x| title.split_once(x))
+        .map(|(left, right)| (left.trim(), right.trim()))
+        .filter(|(left, right)| !left.is_empty() && !right.is_empty());
+    let (artist, title) = match split {
+        // only trust the split when the tags did not already name someone else
+        Some((left, right)) if artist.is_empty() || left.eq_ignore_ascii_case(artist) => {
+            (left.to_string(), right.to_string())
+        }
+        _ if artist.is_empty() => (uploader, title),
+        _ => (artist.to_string(), title),
+    };
+    if title.is_empty() || artist.is_empty() {
+        return raw;
+    }
+    Cleaned { title, artist }
+}
+
+// "Artist - Topic" is how youtube names auto generated music channels
+pub fn clean_uploader(uploader: &str) -> String {
+    if !CONFIG.clean_titles {
+        return uploader.to_string();
+    }
+    let cleaned = uploader.trim().trim_end_matches(" - Topic");
+    let cleaned = cleaned.strip_suffix("VEVO").unwrap_or(cleaned).trim();
+    if cleaned.is_empty() {
+        uploader.to_string()
+    } else {
+        cleaned.to_string()
+    }
+}
+
+// `extra` is the configured noise on top of the built in words
+fn is_noise(text: &str, extra: &[String]) -> bool {
+    let text = text.to_lowercase();
+    let words: Vec<&str> = text.split_whitespace().collect();
+    NOISE
+        .iter()
+        .map(|x| x.to_string())
+        .chain(extra.iter().map(|x| x.to_lowercase()))
+        .any(|noise| {
+            if noise.contains(' ') {
+                text.contains(&noise)
+            } else {
+                words.contains(&noise.as_str())
+            }
+        })
+}
+
+fn strip_noise(title: &str, extra: &[String]) -> String {
+    let mut title = title.to_string();
+    for (open, close) in BRACKETS {
+        let mut kept = String::new();
+        let mut rest = title.as_str();
+        while let Some(start) = rest.find(open) {
+            let Some(end) = rest[start..].find(close) else {
+                break;
+            };
+            let (before, group) = (&rest[..start], &rest[start..start + end + close.len_utf8()]);
+            kept.push_str(before);
+            let inner = &group[open.len_utf8()..group.len() - close.len_utf8()];
+            if !is_noise(inner, extra) {
+                kept.push_str(group);
+            }
+            rest = &rest[start + end + close.len_utf8()..];
+        }
+        kept.push_str(rest);
+        title = kept;
+    }
+    let mut words: Vec<&str> = title.split_whitespace().collect();
+    while words.len() > 1
+        && words
+            .last()
+            .is_some_and(|x| TRAILING_NOISE.contains(&x.to_lowercase().as_str()))
+    {
+        words.pop();
+    }
+    let title = words.join(" ");
+    title
+        .trim_end_matches(|c: char| matches!(c, '-' | '|' | '–' | '—') || c.is_whitespace())
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cleaned(title: &str, artist: &str) -> Cleaned {
+        Cleaned {
+            title: title.to_string(),
+            artist: artist.to_string(),
+        }
+    }
+
+    #[test]
+    fn titles() {
+        // title, artist tag, uploader and what they should become
+        let cases = [
+            (
+                "Rick Astley - Never Gonna Give You Up (Official Video)",
+                "",
+                "Rick Astley",
+                cleaned("Never Gonna Give You Up", "Rick Astley"),
+            ),
+            (
+                "Daft Punk – Get Lucky [Official Audio] HD",
+                "",
+                "DaftPunkVEVO",
+                cleaned("Get Lucky", "Daft Punk"),
+            ),
+            (
+                "Some Song [Lyrics]",
+                "",
+                "Some Band - Topic",
+                cleaned("Some Song", "Some Band"),
+            ),
+            (
+                "Some Song (Lyric Video) | 4K",
+                "",
+                "Some Band",
+                cleaned("Some Song", "Some Band"),
+            ),
+            (
+                "Artist - Song ft. Guest (Official Music Video)",
+                "",
+                "Label",
+                cleaned("Song ft. Guest", "Artist"),
+            ),
+            (
+                "Artist - Song (feat. Guest) [Official Video]",
+                "",
+                "Label",
+                cleaned("Song (feat. Guest)", "Artist"),
+            ),
+            (
+                "Artist feat. Guest - Song",
+                "",
+                "Label",
+                cleaned("Song", "Artist feat. Guest"),
+            ),
+            (
+                "Song Title (Official Audio)",
+                "Tagged Artist",
+                "Uploader",
+                cleaned("Song Title", "Tagged Artist"),
+            ),
+            // the tag names the artist, so the left side is kept when it names someone else
+            (
+                "Other Name - Song",
+                "Tagged Artist",
+                "Uploader",
+                cleaned("Other Name - Song", "Tagged Artist"),
+            ),
+            (
+                "tagged artist - Song",
+                "Tagged Artist",
+                "Uploader",
+                cleaned("Song", "tagged artist"),
+            ),
+            (
+                "【MV】Song【Official】",
+                "",
+                "Singer",
+                cleaned("Song", "Singer"),
+            ),
+        ];
+        for (title, artist, uploader, expected) in cases {
+            assert_eq!(clean(title, artist, uploader), expected, "{title}");
+        }
+    }
+
+    #[test]
+    fn left_alone() {
+        let cases = [
+            ("Song (Live at Wembley)", "Band"),
+            ("Song (Acoustic)", "Band"),
+            ("Song [Remix]", "Band"),
+            ("Videotape", "Radiohead"),
+            ("Audio Audio", "Band"),
+            ("HD", "Band"),
+            ("Song (unclosed", "Band"),
+        ];
+        for (title, uploader) in cases {
+            assert_eq!(
+                clean(title, "", uploader),
+                cleaned(title, uploader),
+                "{title}"
+            );
+        }
+        // nothing is left after cleaning, so the raw values are kept
+        assert_eq!(
+            clean("(Official Video)", "", "Band"),
+            cleaned("(Official Video)", "Band")
+        );
+        assert_eq!(clean(" - Song", "", ""), cleaned(" - Song", ""));
+    }
+
+    #[test]
+    fn uploaders() {
+        assert_eq!(clean_uploader("Some Band - Topic"), "Some Band");
+        assert_eq!(clean_uploader("SomeBandVEVO"), "SomeBand");
+        assert_eq!(clean_uploader("VEVO"), "VEVO");
+        assert_eq!(clean_uploader("Plain Channel"), "Plain Channel");
+    }
+
+    #[test]
+    fn custom_noise() {
+        let extra = ["Remastered 2011".to_string(), "NIGHTCORE".to_string()];
+        assert_eq!(strip_noise("Song (Remastered 2011)", &extra), "Song");
+        assert_eq!(strip_noise("Song [nightcore]", &extra), "Song");
+        assert_eq!(
+            strip_noise("Song (Remastered 2011)", &[]),
+            "Song (Remastered 2011)"
+        );
+        // single words have to match a whole word
+        assert_eq!(
+            strip_noise("Song (Nightcores)", &extra),
+            "Song (Nightcores)"
+        );
+    }
+}
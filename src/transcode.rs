@@ -0,0 +1,392 @@
+use crate::{types::User, CONFIG, TRANSCODES, TRANSCODES_USED};
+use actix_web::rt;
+use derive_more::Display;
+use log::{info, warn};
+use serde::Deserialize;
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    sync::{Arc, PoisonError},
+    time::{Duration, SystemTime},
+};
+use tokio::{process::Command, sync::watch, time::timeout};
+
+const FFMPEG: &str = "ffmpeg";
+const CACHE_DIR: &str = "transcodes";
+const MB: u64 = 1024 * 1024;
+// a variant served this recently is being opened for a response and is never evicted
+const EVICT_GRACE: Duration = Duration::from_secs(30);
+// how long a request waits on an encode before it is asked to come back, and how much later
+const ENCODE_WAIT: Duration = Duration::from_secs(15);
+pub const RETRY_AFTER_SEC: u64 = 10;
+// kbps, anything else would fill the cache with variants nobody can tell apart
+pub const BITRATES: [u32; 7] = [64, 96, 128, 160, 192, 256, 320];
+
+#[derive(Debug, Display)]
+pub enum TranscodeError {
+    #[display(fmt = "unknown format {}, use original, mp3 or opus", _0)]
+    InvalidFormat(String),
+    #[display(fmt = "unsupported bitrate {}, use one of {:?}", _0, BITRATES)]
+    InvalidBitrate(u32),
+    #[display(fmt = "{} is not installed", FFMPEG)]
+    NotInstalled,
+    #[display(fmt = "ffmpeg exited with {}", _0)]
+    Failed(String),
+    #[display(fmt = "the encode stopped without a result")]
+    Aborted,
+    #[display(fmt = "io error: {}", _0)]
+    Io(io::Error),
+}
+
+impl std::error::Error for TranscodeError {}
+
+impl From<io::Error> for TranscodeError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+// the result of an encode that is still running, none until it is done
+pub type Encoding = watch::Receiver<Option<Result<(), Arc<TranscodeError>>>>;
+
+// takes a variant out of TRANSCODES when its encode ends, also when the encode panics
+struct InFlight(String);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        TRANSCODES
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.0);
+    }
+}
+
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    #[display(fmt = "mp3")]
+    Mp3,
+    #[display(fmt = "opus")]
+    Opus,
+}
+
+impl Format {
+    // none means the file in songs/ as it is
+    pub fn parse(format: &str) -> Result<Option<Self>, TranscodeError> {
+        match format {
+            "original" => Ok(None),
+            "mp3" => Ok(Some(Self::Mp3)),
+            "opus" => Ok(Some(Self::Opus)),
+            v => Err(TranscodeError::InvalidFormat(v.to_string())),
+        }
+    }
+
+    pub fn mime(&self) -> &'static str {
+        match self {
+            Self::Mp3 => "audio/mpeg",
+            Self::Opus => "audio/ogg",
+        }
+    }
+
+    fn default_bitrate(&self) -> u32 {
+        match self {
+            Self::Mp3 => 128,
+            Self::Opus => 96,
+        }
+    }
+
+    fn ffmpeg_args(&self) -> [&'static str; 4] {
+        match self {
+            Self::Mp3 => ["-c:a", "libmp3lame", "-f", "mp3"],
+            Self::Opus => ["-c:a", "libopus", "-f", "ogg"],
+        }
+    }
+}
+
+pub fn check_bitrate(bitrate: u32) -> Result<u32, TranscodeError> {
+    if BITRATES.contains(&bitrate) {
+        Ok(bitrate)
+    } else {
+        Err(TranscodeError::InvalidBitrate(bitrate))
+    }
+}
+
+// ?format=opus&bitrate=96 on the stream route, missing values come from the user's preference
+#[derive(Deserialize)]
+pub struct TranscodeQuery {
+    pub format: Option<String>,
+    pub bitrate: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Variant {
+    pub format: Format,
+    pub bitrate: u32,
+}
+
+impl Variant {
+    // what to stream to `user`, none streams the original file
+    pub fn resolve(query: &TranscodeQuery, user: &User) -> Result<Option<Self>, TranscodeError> {
+        let format = query.format.as_deref().or(user.stream_format.as_deref());
+        let Some(format) = format.map(Format::parse).transpose()?.flatten() else {
+            return Ok(None);
+        };
+        let bitrate = query
+            .bitrate
+            .or(user.stream_bitrate.map(|x| x as u32))
+            .unwrap_or(format.default_bitrate());
+        Ok(Some(Self {
+            format,
+            bitrate: check_bitrate(bitrate)?,
+        }))
+    }
+
+    pub fn cache_path(&self, id: &str) -> PathBuf {
+        Path::new(CACHE_DIR).join(format!("{id}.{}.{}", self.bitrate, self.format))
+    }
+
+    // the cached file for this variant of songs/{id}.{format}, encoding it first if it is not
+    // cached yet, every request for a variant that is being encoded waits on that one encode,
+    // none when it takes longer than ENCODE_WAIT and the encode keeps going without the request
+    pub async fn load_or_encode(
+        &self,
+        id: &str,
+        format: &str,
+    ) -> Result<Option<PathBuf>, Arc<TranscodeError>> {
+        let path = self.cache_path(id);
+        if mark_used(&path) {
+            return Ok(Some(path));
+        }
+        let mut encoding = self.start(format!("songs/{id}.{format}"), &path);
+        let result = match timeout(ENCODE_WAIT, encoding.wait_for(Option::is_some)).await {
+            Err(_) => return Ok(None),
+            // the sender is gone without a result when the encode panicked
+            Ok(Err(_)) => return Err(Arc::new(TranscodeError::Aborted)),
+            Ok(Ok(result)) => result.clone(),
+        };
+        result.unwrap_or(Err(Arc::new(TranscodeError::Aborted)))?;
+        mark_used(&path);
+        Ok(Some(path))
+    }
+
+    // join the encode of this variant or spawn it, it runs on its own so a listener that gives
+    // up does not kill ffmpeg for everyone else waiting
+    fn start(&self, source: String, path: &Path) -> Encoding {
+        let key = path.to_string_lossy().to_string();
+        let mut transcodes = TRANSCODES.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(encoding) = transcodes.get(&key) {
+            return encoding.clone();
+        }
+        let (done, encoding) = watch::channel(None);
+        transcodes.insert(key.clone(), encoding.clone());
+        drop(transcodes);
+        let in_flight = InFlight(key);
+        let variant = *self;
+        let path = path.to_path_buf();
+        rt::spawn(async move {
+            let _in_flight = in_flight;
+            // another encode of it may have finished between the lookup and taking the key
+            let result = if path.exists() {
+                Ok(())
+            } else {
+                variant.encode(&source, &path).await
+            };
+            match &result {
+                Ok(()) => {
+                    mark_used(&path);
+                    let max_bytes = CONFIG.transcode_cache_mb as u64 * MB;
+                    if let Err(e) = evict(Path::new(CACHE_DIR), max_bytes, &path) {
+                        warn!("could not trim the transcode cache: {e}");
+                    }
+                }
+                Err(e) => warn!("could not transcode {source}: {e}"),
+            }
+            let _ = done.send(Some(result.map_err(Arc::new)));
+        });
+        encoding
+    }
+
+    async fn encode(&self, source: &str, path: &Path) -> Result<(), TranscodeError> {
+        fs::create_dir_all(CACHE_DIR)?;
+        // written next to the cache and moved in place so a half written file is never served
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".part");
+        let partial = PathBuf::from(partial);
+        let output = Command::new(FFMPEG)
+            .kill_on_drop(true)
+            .args([
+                "-y",
+                "-loglevel",
+                "error",
+                "-i",
+                source,
+                "-vn",
+                "-map_metadata",
+                "-1",
+            ])
+            .args(self.format.ffmpeg_args())
+            .args(["-b:a", &format!("{}k", self.bitrate)])
+            .arg(&partial)
+            .output()
+            .await
+            .map_err(|e| match e.kind() {
+                ErrorKind::NotFound => TranscodeError::NotInstalled,
+                _ => TranscodeError::Io(e),
+            })?;
+        if !output.status.success() {
+            let _ = fs::remove_file(&partial);
+            return Err(TranscodeError::Failed(output.status.to_string()));
+        }
+        fs::rename(&partial, path)?;
+        info!("transcoded {source} to {}", path.display());
+        Ok(())
+    }
+}
+
+// a hit moves the variant to the back of the cache, the file is left alone so the validators
+// a client got from an earlier response still match, false when the variant is not cached
+fn mark_used(path: &Path) -> bool {
+    let mut used = TRANSCODES_USED
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    if !path.is_file() {
+        return false;
+    }
+    used.insert(path.to_path_buf(), SystemTime::now());
+    true
+}
+
+// delete the least recently used variants in `dir` until it fits in `max_bytes`, `keep` and
+// anything served within EVICT_GRACE are about to be sent and stay even when the cache is too
+// big without them, variants not served since the server started count from when they were
+// written
+pub fn evict(dir: &Path, max_bytes: u64, keep: &Path) -> io::Result<()> {
+    if !dir.exists() {
+        return Ok(());
+    }
+    let mut used = TRANSCODES_USED
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    let recent = SystemTime::now() - EVICT_GRACE;
+    let mut files = vec![];
+    let mut size = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let done = entry.path().extension().is_some_and(|x| x != "part");
+        if !metadata.is_file() || !done {
+            continue;
+        }
+        size += metadata.len();
+        let last_used = match used.get(&entry.path()) {
+            Some(v) => *v,
+            None => metadata.modified()?,
+        };
+        if entry.path() != keep && last_used < recent {
+            files.push((last_used, metadata.len(), entry.path()));
+        }
+    }
+    files.sort();
+    for (_, len, path) in files {
+        if size <= max_bytes {
+            break;
+        }
+        fs::remove_file(&path)?;
+        used.remove(&path);
+        size = size.saturating_sub(len);
+    }
+    Ok(())
+}
+
+// drop every cached variant of a song, its audio is gone or changed
+pub fn remove(id: &str) {
+    let Ok(entries) = fs::read_dir(CACHE_DIR) else {
+        return;
+    };
+    let mut used = TRANSCODES_USED
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    let prefix = format!("{id}.");
+    for entry in entries.filter_map(|x| x.ok()) {
+        if entry.file_name().to_string_lossy().starts_with(&prefix) {
+            let _ = fs::remove_file(entry.path());
+            used.remove(&entry.path());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    fn cached(dir: &Path, name: &str, age: u64) -> PathBuf {
+        let path = dir.join(name);
+        let file = File::create(&path).unwrap();
+        file.set_len(MB).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(age))
+            .unwrap();
+        path
+    }
+
+    #[test]
+    fn evict_oldest_first() {
+        let dir = std::env::temp_dir().join("seanify-evict-test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let oldest = cached(&dir, "a.128.mp3", 600);
+        let older = cached(&dir, "b.128.mp3", 300);
+        let old = cached(&dir, "c.96.opus", 120);
+        let playing = cached(&dir, "d.96.opus", 5);
+        let part = cached(&dir, "e.96.opus.part", 900);
+        let new = cached(&dir, "f.320.mp3", 0);
+        evict(&dir, 3 * MB, &new).unwrap();
+        assert!(!oldest.exists());
+        assert!(!older.exists());
+        assert!(old.exists());
+        assert!(playing.exists());
+        assert!(part.exists());
+        assert!(new.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn recently_touched_stays() {
+        let dir = std::env::temp_dir().join("seanify-evict-grace-test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let hit = cached(&dir, "a.128.mp3", 600);
+        let other = cached(&dir, "b.128.mp3", 300);
+        let new = cached(&dir, "c.128.mp3", 0);
+        let modified = fs::metadata(&hit).unwrap().modified().unwrap();
+        assert!(mark_used(&hit));
+        assert!(!mark_used(&dir.join("missing.128.mp3")));
+        // the file times are what the etag is made of
+        assert_eq!(fs::metadata(&hit).unwrap().modified().unwrap(), modified);
+        evict(&dir, 0, &new).unwrap();
+        assert!(hit.exists());
+        assert!(!other.exists());
+        assert!(new.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn one_encode_per_variant() {
+        let variant = Variant {
+            format: Format::Opus,
+            bitrate: 64,
+        };
+        let key = variant
+            .cache_path("no such song")
+            .to_string_lossy()
+            .to_string();
+        let (a, b) = futures::join!(
+            variant.load_or_encode("no such song", "mp3"),
+            variant.load_or_encode("no such song", "mp3"),
+        );
+        // there is no source file so the one encode fails for both requests
+        assert!(Arc::ptr_eq(&a.unwrap_err(), &b.unwrap_err()));
+        rt::task::yield_now().await;
+        assert!(!TRANSCODES.lock().unwrap().contains_key(&key));
+    }
+}
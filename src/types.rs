@@ -1,15 +1,24 @@
 use crate::{
-    fetch_db,
-    fuzzy::{fuzzy_search_best_n, SearchType},
-    youtube::VideoData,
-    CONFIG, DB, SONG_SEARCH,
+    audio::{self, AudioTags},
+    catalog, chapters, covers,
+    fuzzy::{fuzzy_search_sorted, SearchType},
+    library, loudness,
+    lyrics::{Lyrics, SearchableLyrics},
+    media::ExtractorError,
+    quota::Quota,
+    time, titles, transcode, waveform,
+    youtube::{Progress, Source, SourceError, VideoData},
+    CONFIG, DOWNLOAD_NOTIFY, EXTRACTOR, SONG_SEARCH,
 };
+use actix_web::web;
 use anyhow::{anyhow, Result};
 use derive_more::Display;
+use log::warn;
 use serde::{Deserialize, Serialize};
-use sqlx::{pool::PoolConnection, query, query_as, Postgres};
-use std::future::Future;
-use std::{collections::VecDeque, fs, process::Command};
+use sqlx::{pool::PoolConnection, query, query_as, Connection, Postgres};
+use std::collections::HashSet;
+use std::fs;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 // The number of songs that we report back with last played
 // const LAST_PLAYED_LENGTH: usize = 30;
@@ -37,6 +46,53 @@ pub struct Config {
     pub retries: usize,
     #[serde(default = "default_yt_timeout_sec")]
     pub yt_timeout_sec: String,
+    #[serde(default = "default_download_workers")]
+    pub download_workers: usize,
+    // yt-dlp or fake
+    #[serde(default = "default_extractor")]
+    pub extractor: String,
+    #[serde(default = "default_extractor_timeout_sec")]
+    pub extractor_timeout_sec: u64,
+    // where the fake extractor copies songs from
+    #[serde(default = "default_fixtures_dir")]
+    pub fixtures_dir: String,
+    // free up space by removing unused songs when the library limits are reached
+    #[serde(default)]
+    pub evict_unused_songs: bool,
+    // per user defaults, admins can override them for single users
+    #[serde(default = "default_user_max_songs")]
+    pub user_max_songs: usize,
+    #[serde(default = "default_user_max_storage_mb")]
+    pub user_max_storage_mb: usize,
+    #[serde(default = "default_user_jobs_per_hour")]
+    pub user_jobs_per_hour: usize,
+    // split "Artist - Title" and strip noise like "(Official Audio)" from downloaded titles
+    #[serde(default = "default_clean_titles")]
+    pub clean_titles: bool,
+    // extra words that mark a bracketed part of a title as noise, comma separated
+    #[serde(default)]
+    pub title_noise: Vec<String>,
+    // subtitle languages yt-dlp saves as lyrics, in the --sub-langs format
+    #[serde(default = "default_subtitle_langs")]
+    pub subtitle_langs: String,
+    // age limit for users that did not pick one, youtube uses 0 and 18
+    #[serde(default = "default_max_age_limit")]
+    pub default_max_age_limit: i32,
+    // cut downloads that have chapters into one song per chapter
+    #[serde(default)]
+    pub split_chapters: bool,
+    // key for signed media urls, a random one is made at startup when this is empty
+    #[serde(default)]
+    pub media_signing_key: String,
+    // how long a signed media url stays valid
+    #[serde(default = "default_media_url_ttl_sec")]
+    pub media_url_ttl_sec: u64,
+    // size cap of the transcode cache, the least recently streamed variants are dropped first
+    #[serde(default = "default_transcode_cache_mb")]
+    pub transcode_cache_mb: usize,
+    // largest audio file that can be uploaded directly
+    #[serde(default = "default_max_upload_mb")]
+    pub max_upload_mb: usize,
 }
 
 fn default_host() -> String {
@@ -75,6 +131,58 @@ fn default_yt_timeout_sec() -> String {
     String::from("3")
 }
 
+fn default_download_workers() -> usize {
+    2
+}
+
+fn default_extractor() -> String {
+    String::from("yt-dlp")
+}
+
+fn default_extractor_timeout_sec() -> u64 {
+    600
+}
+
+fn default_fixtures_dir() -> String {
+    String::from("fixtures")
+}
+
+fn default_user_max_songs() -> usize {
+    500
+}
+
+fn default_user_max_storage_mb() -> usize {
+    2048
+}
+
+fn default_user_jobs_per_hour() -> usize {
+    30
+}
+
+fn default_clean_titles() -> bool {
+    true
+}
+
+fn default_subtitle_langs() -> String {
+    String::from("en.*")
+}
+
+fn default_max_age_limit() -> i32 {
+    18
+}
+
+fn default_media_url_ttl_sec() -> u64 {
+    300
+}
+
+fn default_transcode_cache_mb() -> usize {
+    1024
+}
+
+fn default_max_upload_mb() -> usize {
+    200
+}
+
 impl Default for Config {
     fn default() -> Self {
         envy::from_env::<Config>().expect("Provide missing environment variables for Config")
@@ -124,6 +232,14 @@ pub struct User {
     pub analytics: bool,
     pub admin: bool,
     pub lastupdate: String,
+    // highest age limit of songs this user gets to see, none uses the server default
+    #[serde(default)]
+    pub max_age_limit: Option<i32>,
+    // default format and bitrate for streaming, see transcode.rs
+    #[serde(default)]
+    pub stream_format: Option<String>,
+    #[serde(default)]
+    pub stream_bitrate: Option<i32>,
 }
 
 // no need to convert structs just to do a tiny operation
@@ -135,6 +251,29 @@ impl User {
         self.last_played.push(new_song);
         &self.last_played
     }
+    // a listen of `song`, it goes to the front of the user's history and keeps the song from
+    // being evicted
+    pub async fn record_play(
+        &mut self,
+        db: &mut PoolConnection<Postgres>,
+        song: &str,
+    ) -> Result<()> {
+        query!(
+            "update songs set last_played = extract(epoch from now())::bigint where id = $1",
+            song
+        )
+        .execute(&mut *db)
+        .await?;
+        self.now_playing(song.to_string());
+        query!(
+            "update users set last_played = $1 where id = $2",
+            &self.last_played,
+            self.id
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
     pub fn follow(&mut self, follower: String) {
         self.followers.push(follower);
     }
@@ -161,6 +300,10 @@ impl User {
         None
     }
 
+    pub fn age_limit(&self) -> i32 {
+        self.max_age_limit.unwrap_or(CONFIG.default_max_age_limit)
+    }
+
     pub fn like(&mut self, id: String) {
         self.likes.push(id);
     }
@@ -169,27 +312,431 @@ impl User {
     }
 }
 
-#[derive(Default)]
-pub struct DownloadCache(VecDeque<(String, String)>);
+#[derive(Debug, Display, PartialEq, Eq)]
+pub enum JobState {
+    #[display(fmt = "pending")]
+    Pending,
+    #[display(fmt = "running")]
+    Running,
+    #[display(fmt = "done")]
+    Done,
+    #[display(fmt = "failed")]
+    Failed,
+    #[display(fmt = "cancelled")]
+    Cancelled,
+}
+
+#[derive(Debug, Display, PartialEq, Eq, Clone, Copy)]
+pub enum JobKind {
+    #[display(fmt = "download")]
+    Download,
+    // fetch a fresh info.json for an existing song
+    #[display(fmt = "refresh")]
+    Refresh,
+    // fetch the audio of an existing song again, along with its metadata
+    #[display(fmt = "redownload")]
+    Redownload,
+}
 
-impl DownloadCache {
-    pub fn append(&mut self, url: String, user: String) {
-        self.0.push_front((url, user));
+impl JobKind {
+    pub fn parse(kind: &str) -> Self {
+        match kind {
+            "refresh" => Self::Refresh,
+            "redownload" => Self::Redownload,
+            _ => Self::Download,
+        }
     }
-    pub async fn cycle(&mut self, db: &mut PoolConnection<Postgres>) {
-        if let Some((url, user)) = self.0.pop_back() {
-            // TODO ws broadcast
-            Song::from_url(&url, db, user).await;
+}
+
+#[derive(Serialize)]
+pub struct DownloadJob {
+    pub id: i64,
+    pub url: String,
+    pub requester: String,
+    pub state: String,
+    pub attempts: i32,
+    pub error: Option<String>,
+    // id of the song that was added once the job is done
+    pub song: Option<String>,
+    pub created: i64,
+    pub updated: i64,
+    // set when the job was queued as part of a playlist import
+    pub import_id: Option<i64>,
+    pub kind: String,
+}
+
+impl DownloadJob {
+    pub async fn create(
+        db: &mut PoolConnection<Postgres>,
+        url: &str,
+        requester: &str,
+        import_id: Option<i64>,
+    ) -> Result<Self> {
+        let job = query_as!(
+            DownloadJob,
+            r#"insert into download_jobs(url, requester, state, created, updated, import_id)
+            values($1, $2, $3, extract(epoch from now())::bigint, extract(epoch from now())::bigint, $4)
+            returning *"#,
+            url,
+            requester,
+            JobState::Pending.to_string(),
+            import_id
+        )
+        .fetch_one(db)
+        .await?;
+        // wake up a download worker if they are all idle
+        DOWNLOAD_NOTIFY.notify_one();
+        Ok(job)
+    }
+
+    // queue a refresh or redownload of a song that is already in the library
+    pub async fn create_refetch(
+        db: &mut PoolConnection<Postgres>,
+        song: &Song,
+        requester: &str,
+        kind: JobKind,
+    ) -> Result<Self> {
+        let job = query_as!(
+            DownloadJob,
+            r#"insert into download_jobs(url, requester, state, created, updated, song, kind)
+            values($1, $2, $3, extract(epoch from now())::bigint, extract(epoch from now())::bigint, $4, $5)
+            returning *"#,
+            song.url,
+            requester,
+            JobState::Pending.to_string(),
+            song.id,
+            kind.to_string()
+        )
+        .fetch_one(db)
+        .await?;
+        DOWNLOAD_NOTIFY.notify_one();
+        Ok(job)
+    }
+
+    pub async fn from_id(
+        db: &mut PoolConnection<Postgres>,
+        id: i64,
+        requester: &str,
+    ) -> Option<Self> {
+        query_as!(
+            DownloadJob,
+            "select * from download_jobs where id = $1 and requester = $2",
+            id,
+            requester
+        )
+        .fetch_optional(db)
+        .await
+        .ok()
+        .flatten()
+    }
+
+    pub async fn for_user(db: &mut PoolConnection<Postgres>, requester: &str) -> Vec<Self> {
+        query_as!(
+            DownloadJob,
+            "select * from download_jobs where requester = $1 order by id desc",
+            requester
+        )
+        .fetch_all(db)
+        .await
+        .unwrap_or_default()
+    }
+
+    // take the oldest pending job, skip locked rows so workers never grab the same job
+    pub async fn claim(db: &mut PoolConnection<Postgres>) -> Result<Option<Self>> {
+        Ok(query_as!(
+            DownloadJob,
+            r#"update download_jobs set
+                state = $1,
+                attempts = attempts + 1,
+                updated = extract(epoch from now())::bigint
+            where id = (
+                select id from download_jobs
+                where state = $2
+                order by id
+                limit 1
+                for update skip locked
+            )
+            returning *"#,
+            JobState::Running.to_string(),
+            JobState::Pending.to_string()
+        )
+        .fetch_optional(db)
+        .await?)
+    }
+
+    pub async fn finish(&self, db: &mut PoolConnection<Postgres>, song: &str) -> Result<()> {
+        query!(
+            r#"update download_jobs set
+                state = $1,
+                song = $2,
+                error = null,
+                updated = extract(epoch from now())::bigint
+            where id = $3"#,
+            JobState::Done.to_string(),
+            song,
+            self.id
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn fail(&self, db: &mut PoolConnection<Postgres>, error: &str) -> Result<()> {
+        query!(
+            r#"update download_jobs set
+                state = $1,
+                error = $2,
+                updated = extract(epoch from now())::bigint
+            where id = $3"#,
+            JobState::Failed.to_string(),
+            error,
+            self.id
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    // move a job from one state to another, returns false if the job was not in the `from` state
+    async fn transition(
+        db: &mut PoolConnection<Postgres>,
+        id: i64,
+        requester: &str,
+        from: JobState,
+        to: JobState,
+    ) -> bool {
+        matches!(
+            query!(
+                r#"update download_jobs set
+                    state = $1,
+                    updated = extract(epoch from now())::bigint
+                where id = $2 and requester = $3 and state = $4"#,
+                to.to_string(),
+                id,
+                requester,
+                from.to_string()
+            )
+            .execute(db)
+            .await,
+            Ok(v) if v.rows_affected() > 0
+        )
+    }
+
+    pub async fn cancel(db: &mut PoolConnection<Postgres>, id: i64, requester: &str) -> bool {
+        Self::transition(db, id, requester, JobState::Pending, JobState::Cancelled).await
+    }
+
+    pub async fn retry(db: &mut PoolConnection<Postgres>, id: i64, requester: &str) -> bool {
+        let retried =
+            Self::transition(db, id, requester, JobState::Failed, JobState::Pending).await;
+        if retried {
+            DOWNLOAD_NOTIFY.notify_one();
         }
+        retried
     }
-    pub fn clear(&mut self) {
-        self.0.clear();
+
+    pub async fn cancel_all(db: &mut PoolConnection<Postgres>) -> Result<u64> {
+        Ok(query!(
+            "update download_jobs set state = $1 where state = $2",
+            JobState::Cancelled.to_string(),
+            JobState::Pending.to_string()
+        )
+        .execute(db)
+        .await?
+        .rows_affected())
     }
-    pub fn list(&self) -> String {
-        serde_json::to_string(&self.0).unwrap()
+
+    // jobs that were running when the server went down would otherwise be stuck forever
+    pub async fn requeue_running(db: &mut PoolConnection<Postgres>) -> Result<u64> {
+        Ok(query!(
+            "update download_jobs set state = $1 where state = $2",
+            JobState::Pending.to_string(),
+            JobState::Running.to_string()
+        )
+        .execute(db)
+        .await?
+        .rows_affected())
+    }
+}
+
+#[derive(Serialize)]
+pub struct PlaylistImport {
+    pub id: i64,
+    pub requester: String,
+    pub name: String,
+    pub url: String,
+    // every entry of the source playlist in order, including songs we already had
+    pub songs: Vec<String>,
+    pub create_playlist: bool,
+    pub finished: bool,
+    pub created: i64,
+}
+
+impl PlaylistImport {
+    // expand a playlist or channel and queue a download for every entry that is not in the
+    // library yet, the whole import is refused when it does not fit in the requester's quota or
+    // the library, returns the ids of the queued jobs
+    pub async fn start(
+        db: &mut PoolConnection<Postgres>,
+        source: &Source,
+        user: &User,
+        create_playlist: bool,
+    ) -> Result<Vec<i64>> {
+        let url = source.canonical_url();
+        let info = EXTRACTOR.expand(source.id(), &url).await?;
+        // a video that is in the playlist twice is downloaded and listed once
+        let mut seen = HashSet::new();
+        let ids: Vec<String> = info
+            .entries
+            .into_iter()
+            .map(|x| x.id)
+            .filter(|x| seen.insert(x.clone()))
+            .collect();
+        let existing: Vec<String> = query!("select id from songs where id = any($1)", &ids)
+            .fetch_all(&mut *db)
+            .await?
+            .into_iter()
+            .map(|x| x.id)
+            .collect();
+        let new: Vec<&String> = ids.iter().filter(|x| !existing.contains(x)).collect();
+        Quota::check(db, user, new.len(), true).await?;
+        let import = query_as!(
+            PlaylistImport,
+            r#"insert into playlist_imports(requester, name, url, songs, create_playlist, created)
+            values($1, $2, $3, $4, $5, extract(epoch from now())::bigint)
+            returning *"#,
+            user.id,
+            info.title,
+            url,
+            &ids,
+            create_playlist
+        )
+        .fetch_one(&mut *db)
+        .await?;
+        // the songs of the import are safe from eviction once its row exists
+        if let Err(e) = library::ensure_capacity(db, new.len()).await {
+            query!("delete from playlist_imports where id = $1", import.id)
+                .execute(&mut *db)
+                .await?;
+            return Err(e.into());
+        }
+        let mut jobs = vec![];
+        for id in new {
+            let url = Source::Video(id.to_string()).canonical_url();
+            jobs.push(
+                DownloadJob::create(db, &url, &user.id, Some(import.id))
+                    .await?
+                    .id,
+            );
+        }
+        // nothing to download so the playlist can be made right away
+        if jobs.is_empty() {
+            Self::finish_if_done(db, import.id).await?;
+        }
+        Ok(jobs)
+    }
+
+    // called whenever a job of the import stops, once none of them are left pending or running
+    // the import is marked finished and the playlist is created if it was asked for
+    pub async fn finish_if_done(db: &mut PoolConnection<Postgres>, id: i64) -> Result<()> {
+        let remaining = query!(
+            "select count(*) from download_jobs where import_id = $1 and state in ($2, $3)",
+            id,
+            JobState::Pending.to_string(),
+            JobState::Running.to_string()
+        )
+        .fetch_one(&mut *db)
+        .await?
+        .count
+        .unwrap_or_default();
+        if remaining > 0 {
+            return Ok(());
+        }
+        let Some(import) = query_as!(
+            PlaylistImport,
+            "update playlist_imports set finished = true where id = $1 and finished = false returning *",
+            id
+        )
+        .fetch_optional(&mut *db)
+        .await? else {
+            // someone else already finished it
+            return Ok(());
+        };
+        if !import.create_playlist {
+            return Ok(());
+        }
+        let Some(user) = User::from_id(db, &import.requester).await else {
+            return Err(anyhow!("user {} no longer exists", import.requester));
+        };
+        // entries that were queued resolve to the song their job ended up with, which can be
+        // an older copy of the same audio, entries we already had are looked up as they are and
+        // songs that failed to download or were deleted since are left out
+        let urls: Vec<String> = import
+            .songs
+            .iter()
+            .map(|x| Source::Video(x.to_string()).canonical_url())
+            .collect();
+        let rows = query!(
+            r#"select s.id, s.duration
+            from unnest($1::text[], $2::text[]) with ordinality e(entry, url, pos)
+            left join download_jobs j on j.import_id = $3 and j.url = e.url
+            join songs s on s.id = case when j.id is null then e.entry
+                                        when j.state = $4 then j.song end
+            order by e.pos"#,
+            &import.songs,
+            &urls,
+            import.id,
+            JobState::Done.to_string()
+        )
+        .fetch_all(&mut *db)
+        .await?;
+        let mut seen = HashSet::new();
+        let songs: Vec<_> = rows
+            .into_iter()
+            .filter(|x| seen.insert(x.id.clone()))
+            .collect();
+        // playlist names are unique across users, fall back to one with the source id in it
+        let title: String = import.name.chars().take(80).collect();
+        let taken = query!(
+            "select count(*) as \"count!\" from playlist where name = $1",
+            title
+        )
+        .fetch_one(&mut *db)
+        .await?
+        .count
+            > 0;
+        let name = if taken {
+            let id = Source::parse(&import.url)
+                .map(|x| x.id().to_string())
+                .unwrap_or_else(|_| import.id.to_string());
+            format!("{title} ({id})")
+        } else {
+            title
+        };
+        let playlist = Playlist {
+            name,
+            public_playlist: true,
+            songs: songs.iter().map(|x| x.id.clone()).collect(),
+            author: user.username,
+            author_id: user.id,
+            edit_list: vec![],
+            description: format!("imported from {}", import.url),
+            likes: vec![],
+            cover: String::new(),
+            duration: (songs.iter().map(|x| x.duration).sum::<f64>() + 0.5) as i64,
+            lastupdate: time!(),
+        };
+        playlist.insert(db).await
     }
 }
 
+pub(crate) fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs() as i64)
+        .unwrap_or_default()
+}
+
 #[derive(Serialize, Clone)]
 pub struct Song {
     pub id: String,
@@ -207,185 +754,728 @@ pub struct Song {
     pub filesize: i64,
     pub added_by: String,
     pub default_search: String,
+    // file extension of the audio in songs/
+    pub format: String,
+    // unix time of the last listen, used to pick songs to evict
+    pub last_played: i64,
+    // unix time the song entered the library
+    pub added: i64,
+    // blake3 hash of the decoded audio
+    pub audio_hash: Option<String>,
+    // integrated loudness in LUFS and the replaygain values derived from it
+    pub loudness: Option<f64>,
+    pub track_gain: Option<f64>,
+    pub track_peak: Option<f64>,
+    // remote thumbnail from the source and whether art was extracted into songs/, none until
+    // the file was looked at, both only go out through covers::SongView
+    #[serde(skip_serializing)]
+    pub thumbnail: String,
+    #[serde(skip_serializing)]
+    pub cover: Option<bool>,
+    // title, artist tag and uploader as they came from the source, before any cleanup
+    pub raw_title: String,
+    pub raw_artist: String,
+    pub raw_uploader: String,
+    // set by the reconcile scan when the audio file is gone from songs/
+    pub missing: bool,
+    // links into the artists and albums tables, see catalog.rs
+    pub artist_id: Option<i64>,
+    pub album_id: Option<i64>,
+    // track number from the tags, orders the songs of an album
+    pub track: Option<i32>,
+    // the video this song was cut out of and which of its chapters it is, see chapters.rs
+    pub parent: Option<String>,
+    pub chapter: Option<i32>,
+}
+
+// fields of a song that its uploader or an admin can correct, missing fields are left alone
+#[derive(Deserialize)]
+pub struct SongEdit {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
 }
 
 #[derive(Debug, Display)]
-enum SongError {
-    #[display(fmt = "metadata extraction failure")]
-    MetadataExtractionFailure,
+pub enum SongError {
+    #[display(fmt = "{}", _0)]
+    InvalidUrl(SourceError),
+    #[display(fmt = "playlists have to be expanded before they are downloaded")]
+    Playlist,
+    #[display(fmt = "{}", _0)]
+    Extractor(ExtractorError),
+    #[display(fmt = "metadata extraction failure: {}", _0)]
+    MetadataExtractionFailure(anyhow::Error),
+    #[display(fmt = "song {} does not exist", _0)]
+    NotFound(String),
+    #[display(fmt = "uploaded songs have no source to fetch them from again")]
+    NoSource,
 }
 
+impl std::error::Error for SongError {}
+
 type SE = SongError;
 impl<'a> Song {
-    // convert to return Result<Error>
-    fn get_id(url: &'a str) -> Option<&'a str> {
-        let id = url.find("?v=");
-        if let Some(v) = id {
-            let split = &url[v..];
-            let end = split.find('?');
-            return Some(if let Some(v) = end {
-                &split[..v]
-            } else {
-                split
-            });
-        }
-        None
-    }
-    // yt-dlp --socket-timeout 3 --embed-thumbnail --audio-format mp3 --extract-audio --output "M3HhNcl2dMA.%(ext)s" --add-metadata --write-info-json https://www.youtube.com/watch\?v\=M3HhNcl2dMA
+    // `import` is set when the song is downloaded as part of a playlist import
     pub async fn from_url(
         url: &'a str,
         db: &mut PoolConnection<Postgres>,
         user: String,
-    ) -> Option<Song> {
-        if let Some(v) = Self::get_id(url) {
-            let _ = fs::create_dir_all("./songs");
-            let mut cmd = Command::new("yt-dlp");
-            cmd.args([
-                "--socket-timeout",
-                &CONFIG.yt_timeout_sec,
-                "--embed-thumbnail",
-                "--audio-format",
-                "mp3",
-                "--retries",
-                &CONFIG.retries.to_string(),
-                "--extract-audio",
-                "--add-metadata",
-                "--output",
-                &format!("songs/{v}.%(ext)s"),
-                "--write-info-json",
-                url,
-            ]);
-            let cmd = cmd.output().expect("yt dlp not installed");
-            if let (true, Ok(s)) = (
-                cmd.status.success(),
-                Self::insert(v.to_string(), db, url, user).await,
-            ) {
-                // ws msg
-                let mut db = fetch_db!();
-                SONG_SEARCH.get().await.write().await.update(&mut db).await;
-                None
-            } else {
-                None
+        import: Option<i64>,
+        progress: impl Fn(Progress) + Send + Sync + 'static,
+    ) -> Result<Song, SE> {
+        let source = Source::parse(url).map_err(SE::InvalidUrl)?;
+        if source.is_collection() {
+            return Err(SE::Playlist);
+        }
+        let url = source.canonical_url();
+        let _ = fs::create_dir_all("./songs");
+        EXTRACTOR
+            .extract(source.id(), &url, Box::new(progress))
+            .await
+            .map_err(SE::Extractor)?;
+        let id = source.id();
+        let (audio_hash, existing) = Self::find_by_audio(db, format!("songs/{id}.mp3")).await;
+        if let Some(existing) = existing {
+            if let Err(e) = Lyrics::import_subtitles(db, id, &existing.id).await {
+                warn!("could not import subtitles for {}: {e}", existing.id);
+            }
+            // same audio under a different id, keep the copy we already have
+            if existing.id != id {
+                Self::remove_files(id, "mp3");
             }
+            return Ok(existing);
+        }
+        let mut song = Self::insert(id.to_string(), db, &url, user, audio_hash)
+            .await
+            .map_err(SE::MetadataExtractionFailure)?;
+        song.analyze(db).await;
+        if let Err(e) = Lyrics::import_subtitles(db, id, id).await {
+            warn!("could not import subtitles for {id}: {e}");
+        }
+        // the import makes its own playlist, one per video would only clutter it
+        if CONFIG.split_chapters {
+            match chapters::split(db, &song, &song.added_by, import.is_none()).await {
+                Ok(_) | Err(chapters::SplitError::NoChapters(_)) => (),
+                Err(e) => warn!("could not split {id} into chapters: {e}"),
+            }
+        }
+        SONG_SEARCH.get().await.write().await.update(db).await;
+        Ok(song)
+    }
+    // fetch an existing song again from its source, keeping its id so likes and playlists are
+    // untouched, with `audio` false only the metadata is refreshed
+    pub async fn refetch(
+        db: &mut PoolConnection<Postgres>,
+        id: &str,
+        audio: bool,
+        progress: impl Fn(Progress) + Send + Sync + 'static,
+    ) -> Result<Song, SE> {
+        let mut song = query_as!(Song, "select * from songs where id = $1", id)
+            .fetch_optional(&mut *db)
+            .await
+            .map_err(|e| SE::MetadataExtractionFailure(e.into()))?
+            .ok_or_else(|| SE::NotFound(id.to_string()))?;
+        if song.url.is_empty() {
+            return Err(SE::NoSource);
+        }
+        let url = Source::parse(&song.url)
+            .map_err(SE::InvalidUrl)?
+            .canonical_url();
+        if audio {
+            EXTRACTOR
+                .extract(&song.id, &url, Box::new(progress))
+                .await
+                .map_err(SE::Extractor)?;
         } else {
-            None
+            EXTRACTOR
+                .metadata(&song.id, &url)
+                .await
+                .map_err(SE::Extractor)?;
+        }
+        song.refresh_metadata(audio)
+            .await
+            .map_err(SE::MetadataExtractionFailure)?;
+        song.update(db)
+            .await
+            .map_err(SE::MetadataExtractionFailure)?;
+        if audio {
+            song.analyze(db).await;
+            if let Err(e) = Lyrics::import_subtitles(db, id, id).await {
+                warn!("could not import subtitles for {id}: {e}");
+            }
+        } else if let Err(e) = catalog::link(db, &mut song).await {
+            warn!("could not add {id} to the catalog: {e}");
+        }
+        SONG_SEARCH.get().await.write().await.update(db).await;
+        Ok(song)
+    }
+
+    // reload the fields that come from the source out of songs/{id}.info.json, and out of the
+    // audio file itself when it was downloaded again
+    async fn refresh_metadata(&mut self, audio: bool) -> Result<()> {
+        let data = VideoData::load_and_replace(&self.id)?;
+        // the artist can change at the source too, the old one would decide the cleanup otherwise
+        if let Some(artist) = data
+            .artist
+            .as_deref()
+            .map(str::trim)
+            .filter(|x| !x.is_empty())
+        {
+            self.raw_artist = artist.to_string();
+        }
+        let cleaned = titles::clean(&data.title, &self.raw_artist, &data.uploader);
+        self.title = cleaned.title;
+        self.artist = cleaned.artist;
+        self.uploader = titles::clean_uploader(&data.uploader);
+        self.raw_title = data.title;
+        self.raw_uploader = data.uploader;
+        self.age_limit = data.age_limit as i32;
+        self.webpage_url = data.webpage_url;
+        self.thumbnail = data.thumbnail;
+        self.was_live = data.was_live;
+        self.upload_date = data.upload_date;
+        self.default_search = format!("{} {} {}", self.title, self.artist, self.album);
+        if audio {
+            let path = format!("songs/{}.mp3", self.id);
+            let meta = mp3_metadata::read_from_file(&path)
+                .map_err(|_| anyhow!("Failed to extract metadata"))?;
+            self.duration = meta.duration.as_secs_f64();
+            self.filesize = fs::metadata(&path)?.len() as i64;
+            self.format = String::from("mp3");
+            self.missing = false;
+            self.audio_hash = Some(web::block(move || audio::content_hash(path)).await??);
+            // the old waveform and transcodes no longer match the audio
+            let _ = fs::remove_file(waveform::cache_path(&self.id));
+            transcode::remove(&self.id);
         }
+        Ok(())
     }
+
+    // write the fields refresh_metadata touches back to the row, playlist durations are
+    // recomputed since the new audio can be a little longer or shorter
+    async fn update(&self, db: &mut PoolConnection<Postgres>) -> Result<()> {
+        query!(
+            r#"update songs set
+                title = $1,
+                artist = $2,
+                uploader = $3,
+                raw_title = $4,
+                raw_uploader = $5,
+                age_limit = $6,
+                webpage_url = $7,
+                thumbnail = $8,
+                was_live = $9,
+                upload_date = $10,
+                default_search = $11,
+                duration = $12,
+                filesize = $13,
+                format = $14,
+                missing = $15,
+                audio_hash = $16,
+                raw_artist = $17
+            where id = $18"#,
+            self.title,
+            self.artist,
+            self.uploader,
+            self.raw_title,
+            self.raw_uploader,
+            self.age_limit,
+            self.webpage_url,
+            self.thumbnail,
+            self.was_live,
+            self.upload_date,
+            self.default_search,
+            self.duration,
+            self.filesize,
+            self.format,
+            self.missing,
+            self.audio_hash,
+            self.raw_artist,
+            self.id
+        )
+        .execute(&mut *db)
+        .await?;
+        query!(
+            r#"update playlist set
+                duration = coalesce((select sum(d.duration) from unnest(songs) u(id)
+                                     join songs d on d.id = u.id), 0)::bigint
+            where $1 = any(songs)"#,
+            self.id
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
     // pass in db handle from from_url
-    async fn insert(
+    pub(crate) async fn insert(
         id: String,
         db: &mut PoolConnection<Postgres>,
         url: &str,
         user_id: String,
+        audio_hash: Option<String>,
     ) -> Result<Song> {
         let meta = match mp3_metadata::read_from_file(format!("songs/{id}.mp3")) {
             Ok(v) => v,
             _ => return Err(anyhow!("Failed to extract metadata")),
         };
         let data = VideoData::load_and_replace(&id)?;
-        if let Some(tag) = meta.tag {
-            let artist = if tag.artist.is_empty() {
-                data.uploader.clone()
-            } else {
-                tag.artist.clone()
-            };
+        if let Some(mut tag) = meta.tag {
+            // id3v1 fields come back with the nul padding of the fixed width fields
+            for field in [&mut tag.title, &mut tag.artist, &mut tag.album] {
+                *field = field.trim_end_matches('\0').to_string();
+            }
+            let cleaned = titles::clean(&data.title, &tag.artist, &data.uploader);
             let new_song = Self {
-                default_search: format!("{} {} {}", &data.title, &tag.artist, &tag.album),
+                default_search: format!("{} {} {}", &cleaned.title, &cleaned.artist, &tag.album),
                 id,
-                title: data.title,
-                uploader: data.uploader,
+                title: cleaned.title,
+                uploader: titles::clean_uploader(&data.uploader),
+                raw_title: data.title,
+                raw_artist: tag.artist,
+                raw_uploader: data.uploader,
                 url: url.to_string(),
-                artist,
+                artist: cleaned.artist,
                 genre: format!("{:?}", tag.genre),
                 album: tag.album,
                 duration: meta.duration.as_secs_f64(),
                 age_limit: data.age_limit as i32,
                 webpage_url: data.webpage_url,
+                thumbnail: data.thumbnail,
+                cover: None,
                 was_live: data.was_live,
                 upload_date: data.upload_date,
                 filesize: data.filesize,
                 added_by: user_id,
+                format: String::from("mp3"),
+                last_played: 0,
+                added: unix_time(),
+                audio_hash,
+                loudness: None,
+                track_gain: None,
+                track_peak: None,
+                missing: false,
+                artist_id: None,
+                album_id: None,
+                track: None,
+                parent: None,
+                chapter: None,
             };
-            let _ = query!(
-                r#"insert into songs(
-                    id,
-                    title,
-                    uploader,
-                    artist,
-                    genre,
-                    album,
-                    url,
-                    duration,
-                    age_limit,
-                    webpage_url,
-                    was_live,
-                    upload_date,
-                    filesize,
-                    added_by,
-                    default_search)
-                values($1,
-                       $2,
-                       $3,
-                       $4,
-                       $5,
-                       $6,
-                       $7,
-                       $8,
-                       $9,
-                       $10,
-                       $11,
-                       $12,
-                       $13,
-                       $14,
-                   $15)"#,
-                new_song.id,
-                new_song.title,
-                new_song.uploader,
-                new_song.artist,
-                new_song.genre,
-                new_song.album,
-                new_song.url,
-                new_song.duration,
-                new_song.age_limit as i32,
-                new_song.webpage_url,
-                new_song.was_live,
-                new_song.upload_date,
-                new_song.filesize as i64,
-                new_song.added_by,
-                new_song.default_search
-            )
-            .execute(db)
-            .await;
+            new_song.save(db).await?;
             Ok(new_song)
         } else {
             Err(anyhow!("failed to read song data"))
         }
     }
+
+    // build a song out of a file that was uploaded directly, the file has to already be
+    // at songs/{id}.{format}
+    pub fn from_upload(id: String, format: &str, filename: &str, user: &User) -> Result<Song> {
+        let path = format!("songs/{id}.{format}");
+        let filesize = fs::metadata(&path)?.len() as i64;
+        let (tags, duration) = if format == "mp3" {
+            let meta = mp3_metadata::read_from_file(&path)
+                .map_err(|_| anyhow!("Failed to extract metadata"))?;
+            let tags = meta.tag.map(|tag| AudioTags {
+                title: tag.title,
+                artist: tag.artist,
+                album: tag.album,
+                genre: format!("{:?}", tag.genre),
+                duration: 0.0,
+            });
+            (tags.unwrap_or_default(), meta.duration.as_secs_f64())
+        } else {
+            let tags = audio::read_tags(&path)?;
+            let duration = tags.duration;
+            (tags, duration)
+        };
+        let title = if tags.title.trim().is_empty() {
+            filename
+                .rsplit_once('.')
+                .map(|(x, _)| x)
+                .unwrap_or(filename)
+                .to_string()
+        } else {
+            tags.title
+        };
+        let artist = if tags.artist.trim().is_empty() {
+            user.username.clone()
+        } else {
+            tags.artist
+        };
+        Ok(Self {
+            default_search: format!("{} {} {}", &title, &artist, &tags.album),
+            id,
+            raw_title: title.clone(),
+            raw_artist: artist.clone(),
+            raw_uploader: user.username.clone(),
+            title,
+            uploader: user.username.clone(),
+            artist,
+            genre: tags.genre,
+            album: tags.album,
+            url: String::new(),
+            duration,
+            age_limit: 0,
+            webpage_url: String::new(),
+            was_live: false,
+            upload_date: String::new(),
+            filesize,
+            added_by: user.id.clone(),
+            format: format.to_string(),
+            last_played: 0,
+            added: unix_time(),
+            audio_hash: None,
+            thumbnail: String::new(),
+            cover: None,
+            loudness: None,
+            track_gain: None,
+            track_peak: None,
+            missing: false,
+            artist_id: None,
+            album_id: None,
+            track: None,
+            parent: None,
+            chapter: None,
+        })
+    }
+
+    // hash the decoded audio at `path` and look for a song that already has the same audio
+    pub async fn find_by_audio(
+        db: &mut PoolConnection<Postgres>,
+        path: String,
+    ) -> (Option<String>, Option<Song>) {
+        let hash = match web::block(move || audio::content_hash(path)).await {
+            Ok(Ok(v)) => v,
+            _ => return (None, None),
+        };
+        let existing = query_as!(
+            Song,
+            "select * from songs where audio_hash = $1 limit 1",
+            hash
+        )
+        .fetch_optional(db)
+        .await
+        .ok()
+        .flatten();
+        (Some(hash), existing)
+    }
+
+    // passes over a freshly added file that the song does not need to be playable, so failures
+    // are only logged
+    pub async fn analyze(&mut self, db: &mut PoolConnection<Postgres>) {
+        if let Err(e) = self.analyze_loudness(db).await {
+            warn!("loudness analysis failed for {}: {e}", self.id);
+        }
+        if let Err(e) = self.extract_cover(db).await {
+            warn!("cover extraction failed for {}: {e}", self.id);
+        }
+        if let Err(e) = catalog::read_track(db, self).await {
+            warn!("could not read the track number of {}: {e}", self.id);
+        }
+        if let Err(e) = catalog::link(db, self).await {
+            warn!("could not add {} to the catalog: {e}", self.id);
+        }
+    }
+
+    // run an EBU R128 analysis of the audio file and store the gain and peak on the row
+    pub async fn analyze_loudness(&mut self, db: &mut PoolConnection<Postgres>) -> Result<()> {
+        let path = format!("songs/{}.{}", self.id, self.format);
+        let loudness = web::block(move || loudness::analyze(path)).await??;
+        self.loudness = Some(loudness.integrated);
+        self.track_gain = Some(loudness.gain());
+        self.track_peak = Some(loudness.peak);
+        query!(
+            "update songs set loudness = $1, track_gain = $2, track_peak = $3 where id = $4",
+            self.loudness,
+            self.track_gain,
+            self.track_peak,
+            self.id
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    pub fn remove_files(id: &str, format: &str) {
+        let _ = fs::remove_file(format!("songs/{id}.{format}"));
+        let _ = fs::remove_file(format!("songs/{id}.info.json"));
+        let _ = fs::remove_file(waveform::cache_path(id));
+        covers::remove(id);
+        transcode::remove(id);
+    }
+
+    // delete songs together with every reference to them in one transaction, the files are
+    // removed once it commits, returns how many playlists had songs taken out of them
+    pub async fn delete(db: &mut PoolConnection<Postgres>, ids: &[String]) -> Result<u64> {
+        let mut tx = db.begin().await?;
+        let removed = query!(
+            "delete from songs where id = any($1) returning id, format",
+            ids
+        )
+        .fetch_all(&mut tx)
+        .await?;
+        let ids: Vec<String> = removed.iter().map(|x| x.id.clone()).collect();
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        // the deleted rows are already gone inside the transaction so the join skips them
+        let playlists = query!(
+            r#"update playlist set
+                songs = array(select s from unnest(songs) with ordinality t(s, i)
+                              where s <> all($1) order by i),
+                duration = coalesce((select sum(d.duration) from unnest(songs) u(id)
+                                     join songs d on d.id = u.id), 0)::bigint
+            where songs && $1"#,
+            &ids
+        )
+        .execute(&mut tx)
+        .await?
+        .rows_affected();
+        query!(
+            r#"update users set
+                likes = array(select s from unnest(likes) with ordinality t(s, i)
+                              where s <> all($1) order by i),
+                last_played = array(select s from unnest(last_played) with ordinality t(s, i)
+                                    where s <> all($1) order by i)
+            where likes && $1 or last_played && $1"#,
+            &ids
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+        for song in removed {
+            Self::remove_files(&song.id, &song.format);
+        }
+        Ok(playlists)
+    }
+
+    // songs above the user's age limit are hidden from them and can not be played
+    pub fn allowed_for(&self, user: &User) -> bool {
+        self.age_limit <= user.age_limit()
+    }
+
+    pub fn can_edit(&self, user: &User) -> bool {
+        self.added_by == user.id || user.admin
+    }
+
+    // apply an edit to the tags of the file so downloads carry the corrections and then to the
+    // row, the catalog and the search index
+    pub async fn edit(&mut self, db: &mut PoolConnection<Postgres>, edit: SongEdit) -> Result<()> {
+        let fields = [
+            (&mut self.title, edit.title),
+            (&mut self.artist, edit.artist),
+            (&mut self.album, edit.album),
+            (&mut self.genre, edit.genre),
+        ];
+        for (field, value) in fields {
+            if let Some(value) = value {
+                *field = value.trim().to_string();
+            }
+        }
+        if self.title.is_empty() {
+            return Err(anyhow!("title can not be empty"));
+        }
+        self.default_search = format!("{} {} {}", self.title, self.artist, self.album);
+        // the tags go first, a file that can not be written leaves the row as it was
+        if self.format == "mp3" {
+            let path = format!("songs/{}.mp3", self.id);
+            let tags = AudioTags {
+                title: self.title.clone(),
+                artist: self.artist.clone(),
+                album: self.album.clone(),
+                genre: self.genre.clone(),
+                duration: self.duration,
+            };
+            web::block(move || audio::write_id3(path, &tags)).await??;
+            self.filesize = fs::metadata(format!("songs/{}.mp3", self.id))?.len() as i64;
+        }
+        query!(
+            r#"update songs set
+                title = $1,
+                artist = $2,
+                album = $3,
+                genre = $4,
+                default_search = $5,
+                filesize = $6
+            where id = $7"#,
+            self.title,
+            self.artist,
+            self.album,
+            self.genre,
+            self.default_search,
+            self.filesize,
+            self.id
+        )
+        .execute(&mut *db)
+        .await?;
+        if let Err(e) = catalog::link(db, self).await {
+            warn!("could not add {} to the catalog: {e}", self.id);
+        }
+        SONG_SEARCH.get().await.write().await.update(db).await;
+        Ok(())
+    }
+
+    // write the embedded art out as small, medium and large images and flag the song as having it
+    pub async fn extract_cover(&mut self, db: &mut PoolConnection<Postgres>) -> Result<()> {
+        let (id, format) = (self.id.clone(), self.format.clone());
+        let result = web::block(move || covers::extract(&id, &format)).await?;
+        // art that can not be read is not tried again on every request either
+        self.cover = Some(*result.as_ref().unwrap_or(&false));
+        query!(
+            "update songs set cover = $1 where id = $2",
+            self.cover,
+            self.id
+        )
+        .execute(db)
+        .await?;
+        result.map(|_| ())
+    }
+
+    pub async fn save(&self, db: &mut PoolConnection<Postgres>) -> Result<()> {
+        query!(
+            r#"insert into songs(
+                id,
+                title,
+                uploader,
+                artist,
+                genre,
+                album,
+                url,
+                duration,
+                age_limit,
+                webpage_url,
+                was_live,
+                upload_date,
+                filesize,
+                added_by,
+                default_search,
+                format,
+                audio_hash,
+                thumbnail,
+                raw_title,
+                raw_artist,
+                raw_uploader,
+                parent,
+                chapter)
+            values($1,
+                   $2,
+                   $3,
+                   $4,
+                   $5,
+                   $6,
+                   $7,
+                   $8,
+                   $9,
+                   $10,
+                   $11,
+                   $12,
+                   $13,
+                   $14,
+                   $15,
+                   $16,
+                   $17,
+                   $18,
+                   $19,
+                   $20,
+                   $21,
+                   $22,
+                   $23)"#,
+            self.id,
+            self.title,
+            self.uploader,
+            self.artist,
+            self.genre,
+            self.album,
+            self.url,
+            self.duration,
+            self.age_limit,
+            self.webpage_url,
+            self.was_live,
+            self.upload_date,
+            self.filesize,
+            self.added_by,
+            self.default_search,
+            self.format,
+            self.audio_hash,
+            self.thumbnail,
+            self.raw_title,
+            self.raw_artist,
+            self.raw_uploader,
+            self.parent,
+            self.chapter
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
 }
 
 pub struct SongSearch {
     songs: Vec<Song>,
+    lyrics: Vec<SearchableLyrics>,
 }
 
 impl SongSearch {
     pub async fn load(db: &mut PoolConnection<Postgres>) -> Self {
         let songs: Vec<Song> = query_as!(Song, "select * from songs")
-            .fetch_all(db)
+            .fetch_all(&mut *db)
             .await
             .unwrap();
-        Self { songs }
+        let lyrics = SearchableLyrics::load(db).await.unwrap_or_default();
+        Self { songs, lyrics }
     }
     pub async fn update(&mut self, db: &mut PoolConnection<Postgres>) {
         let songs: Vec<Song> = query_as!(Song, "select * from songs")
-            .fetch_all(db)
+            .fetch_all(&mut *db)
             .await
             .unwrap();
         self.songs = songs;
+        if let Ok(lyrics) = SearchableLyrics::load(db).await {
+            self.lyrics = lyrics;
+        }
     }
 
+    // songs above `max_age` are left out before the best matches are picked
     #[inline]
-    pub fn search(&self, term: &str, search_type: SearchType, amount: usize) -> Vec<(&Song, f32)> {
-        fuzzy_search_best_n(term, &self.songs, amount, &search_type)
+    pub fn search(
+        &self,
+        term: &str,
+        search_type: SearchType,
+        amount: usize,
+        max_age: i32,
+    ) -> Vec<(&Song, f32)> {
+        if let SearchType::Lyrics = search_type {
+            return fuzzy_search_sorted(term, &self.lyrics, &search_type)
+                .into_iter()
+                .filter_map(|(lyrics, score)| {
+                    let song = self.songs.iter().find(|x| x.id == lyrics.song)?;
+                    Some((song, score))
+                })
+                .filter(|(song, _)| song.age_limit <= max_age)
+                .take(amount)
+                .collect();
+        }
+        fuzzy_search_sorted(term, &self.songs, &search_type)
+            .into_iter()
+            .filter(|(song, _)| song.age_limit <= max_age)
+            .take(amount)
+            .collect()
+    }
+
+    // ids of the songs a user is not allowed to see
+    pub fn restricted_for(&self, user: &User) -> HashSet<&str> {
+        self.songs
+            .iter()
+            .filter(|x| !x.allowed_for(user))
+            .map(|x| x.id.as_str())
+            .collect()
+    }
+
+    pub fn all(&self) -> &[Song] {
+        &self.songs
     }
 
     pub fn get_by_id(&self, id: &str) -> Option<Song> {
@@ -488,6 +1578,42 @@ impl Playlist {
             Err(PlaylistError::InvalidData)
         }
     }
+    pub async fn insert(&self, db: &mut PoolConnection<Postgres>) -> Result<()> {
+        query!(
+            r#"insert into playlist(
+                name,
+                public_playlist,
+                songs,
+                author,
+                author_id,
+                edit_list,
+                description,
+                likes,
+                cover,
+                duration,
+                lastupdate)
+            values($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)"#,
+            self.name,
+            self.public_playlist,
+            &self.songs,
+            self.author,
+            self.author_id,
+            &self.edit_list,
+            self.description,
+            &self.likes,
+            self.cover,
+            self.duration,
+            self.lastupdate
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    // drop the songs the user is not allowed to see from the response
+    pub fn hide_restricted(&mut self, restricted: &HashSet<&str>) {
+        self.songs.retain(|x| !restricted.contains(x.as_str()));
+    }
 }
 
 #[derive(Deserialize, Serialize)]
@@ -0,0 +1,188 @@
+use crate::audio;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::{fs, path::Path};
+
+// number of peaks in each resolution, clients pick whichever is closest to their width
+pub const RESOLUTIONS: [usize; 4] = [64, 256, 1024, 4096];
+// frames folded into one peak before downsampling, fine enough for the largest resolution
+const WINDOW: usize = 256;
+const MAGIC: &[u8; 4] = b"SWF1";
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct Peaks {
+    pub length: usize,
+    // highest absolute sample of each bucket scaled to 0-255
+    pub peaks: Vec<u8>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct Waveform {
+    pub resolutions: Vec<Peaks>,
+}
+
+impl Waveform {
+    // decode the whole file, blocking so run it on the threadpool
+    pub fn generate(path: impl AsRef<Path>) -> Result<Self> {
+        let mut windows: Vec<f32> = vec![];
+        let (mut current, mut frames) = (0f32, 0);
+        audio::decode(path, |samples, channels, _| {
+            for frame in samples.chunks_exact(channels) {
+                current = frame.iter().fold(current, |acc, x| acc.max(x.abs()));
+                frames += 1;
+                if frames == WINDOW {
+                    windows.push(current);
+                    current = 0.0;
+                    frames = 0;
+                }
+            }
+        })?;
+        if frames > 0 {
+            windows.push(current);
+        }
+        if windows.is_empty() {
+            return Err(anyhow!("no audio to build a waveform from"));
+        }
+        let resolutions = RESOLUTIONS
+            .iter()
+            .map(|&length| Peaks::downsample(&windows, length))
+            .collect();
+        Ok(Self { resolutions })
+    }
+
+    // magic, resolution count, then for each resolution a u32 length followed by that many bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(self.resolutions.len() as u8);
+        for peaks in &self.resolutions {
+            bytes.extend_from_slice(&(peaks.length as u32).to_le_bytes());
+            bytes.extend_from_slice(&peaks.peaks);
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let invalid = || anyhow!("invalid waveform file");
+        let rest = bytes.strip_prefix(MAGIC).ok_or_else(invalid)?;
+        let (&count, mut rest) = rest.split_first().ok_or_else(invalid)?;
+        let mut resolutions = vec![];
+        for _ in 0..count {
+            if rest.len() < 4 {
+                return Err(invalid());
+            }
+            let (length, tail) = rest.split_at(4);
+            let length = u32::from_le_bytes(length.try_into()?) as usize;
+            if tail.len() < length {
+                return Err(invalid());
+            }
+            let (peaks, tail) = tail.split_at(length);
+            resolutions.push(Peaks {
+                length,
+                peaks: peaks.to_vec(),
+            });
+            rest = tail;
+        }
+        Ok(Self { resolutions })
+    }
+
+    // read the cached waveform of a song or generate and cache it, blocking
+    pub fn load_or_generate(id: &str, format: &str) -> Result<Self> {
+        let cache = cache_path(id);
+        if let Ok(bytes) = fs::read(&cache) {
+            if let Ok(v) = Self::from_bytes(&bytes) {
+                return Ok(v);
+            }
+        }
+        let waveform = Self::generate(format!("songs/{id}.{format}"))?;
+        fs::write(cache, waveform.to_bytes())?;
+        Ok(waveform)
+    }
+}
+
+impl Peaks {
+    fn downsample(windows: &[f32], length: usize) -> Self {
+        let peaks = (0..length)
+            .map(|i| {
+                let start = i * windows.len() / length;
+                let end = ((i + 1) * windows.len() / length).max(start + 1);
+                let peak = windows[start..end].iter().fold(0f32, |acc, x| acc.max(*x));
+                (peak.min(1.0) * 255.0).round() as u8
+            })
+            .collect();
+        Self { length, peaks }
+    }
+}
+
+pub fn cache_path(id: &str) -> String {
+    format!("songs/{id}.waveform")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn waveform() -> Waveform {
+        Waveform {
+            resolutions: vec![
+                Peaks {
+                    length: 4,
+                    peaks: vec![0, 128, 255, 7],
+                },
+                Peaks {
+                    length: 0,
+                    peaks: vec![],
+                },
+                Peaks {
+                    length: 300,
+                    peaks: (0..300).map(|x| (x % 256) as u8).collect(),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let waveform = waveform();
+        assert_eq!(
+            Waveform::from_bytes(&waveform.to_bytes()).unwrap(),
+            waveform
+        );
+        let empty = Waveform {
+            resolutions: vec![],
+        };
+        assert_eq!(Waveform::from_bytes(&empty.to_bytes()).unwrap(), empty);
+    }
+
+    #[test]
+    fn layout() {
+        let bytes = waveform().to_bytes();
+        assert_eq!(&bytes[..5], b"SWF1\x03");
+        assert_eq!(&bytes[5..9], &4u32.to_le_bytes());
+        assert_eq!(&bytes[9..13], &[0, 128, 255, 7]);
+        assert_eq!(&bytes[13..17], &0u32.to_le_bytes());
+        assert_eq!(&bytes[17..21], &300u32.to_le_bytes());
+        assert_eq!(bytes.len(), 21 + 300);
+    }
+
+    #[test]
+    fn rejects_broken_files() {
+        let bytes = waveform().to_bytes();
+        assert!(Waveform::from_bytes(b"").is_err());
+        assert!(Waveform::from_bytes(b"SWF1").is_err());
+        assert!(Waveform::from_bytes(b"SWF2\x00").is_err());
+        // cut inside a length and inside the peaks
+        assert!(Waveform::from_bytes(&bytes[..7]).is_err());
+        assert!(Waveform::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn downsample() {
+        let windows = [0.1, 0.5, 0.2, 1.5, 0.0, 0.3];
+        assert_eq!(Peaks::downsample(&windows, 3).peaks, [128, 255, 77]);
+        assert_eq!(Peaks::downsample(&windows, 1).peaks, [255]);
+        // more peaks than windows repeats windows instead of leaving gaps
+        let peaks = Peaks::downsample(&[0.0, 1.0], 4);
+        assert_eq!(peaks.length, 4);
+        assert_eq!(peaks.peaks, [0, 0, 255, 255]);
+    }
+}
@@ -1,9 +1,11 @@
 use anyhow::Result;
+use derive_more::Display;
 use serde::{Deserialize, Serialize};
 use std::{
     fs::{self, File},
     io::Write,
 };
+use url::{ParseError, Url};
 
 #[derive(Deserialize, Serialize)]
 pub struct VideoData {
@@ -18,6 +20,20 @@ pub struct VideoData {
     pub was_live: bool,
     pub upload_date: String,
     pub filesize: i64,
+    // only music videos name an artist
+    #[serde(default)]
+    pub artist: Option<String>,
+    // yt-dlp writes null when the video has none
+    #[serde(default)]
+    pub chapters: Option<Vec<Chapter>>,
+}
+
+// seconds from the start of the video
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Chapter {
+    pub start_time: f64,
+    pub end_time: f64,
+    pub title: String,
 }
 
 // #[derive(Debug, Display)]
@@ -42,3 +58,368 @@ impl VideoData {
         Ok(full)
     }
 }
+
+#[derive(Debug, Display, PartialEq, Eq)]
+pub enum SourceError {
+    #[display(fmt = "not a valid url")]
+    InvalidUrl,
+    #[display(fmt = "unsupported host {}", _0)]
+    UnsupportedHost(String),
+    #[display(fmt = "no video or playlist id in url")]
+    MissingId,
+    #[display(fmt = "invalid id {}", _0)]
+    InvalidId(String),
+}
+
+impl std::error::Error for SourceError {}
+
+// what a url points at once every form of youtube link has been normalized
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Video(String),
+    Playlist(String),
+    // either @handle or one of channel/ID, c/NAME and user/NAME
+    Channel(String),
+}
+
+// path prefixes that are followed directly by a video id
+const VIDEO_PATHS: [&str; 5] = ["shorts", "embed", "live", "v", "e"];
+// path prefixes that are followed by a channel id or name
+const CHANNEL_PATHS: [&str; 3] = ["channel", "c", "user"];
+
+impl Source {
+    pub fn parse(url: &str) -> Result<Self, SourceError> {
+        let url = url.trim();
+        let parsed = match Url::parse(url) {
+            Ok(v) => v,
+            Err(ParseError::RelativeUrlWithoutBase) => {
+                Url::parse(&format!("https://{url}")).map_err(|_| SourceError::InvalidUrl)?
+            }
+            Err(_) => return Err(SourceError::InvalidUrl),
+        };
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return Err(SourceError::InvalidUrl);
+        }
+        let host = parsed.host_str().ok_or(SourceError::InvalidUrl)?;
+        let host = host
+            .trim_start_matches("www.")
+            .trim_start_matches("m.")
+            .trim_start_matches("music.");
+        let mut segments = parsed.path_segments().into_iter().flatten();
+        match host {
+            "youtu.be" => Self::video(segments.next()),
+            "youtube.com" | "youtube-nocookie.com" => {
+                let query = |key: &str| {
+                    parsed
+                        .query_pairs()
+                        .find(|(k, _)| k == key)
+                        .map(|(_, v)| v.into_owned())
+                };
+                match segments.next() {
+                    Some("watch") => Self::video(query("v").as_deref()),
+                    Some("playlist") => Self::playlist(query("list").as_deref()),
+                    Some(prefix) if VIDEO_PATHS.contains(&prefix) => Self::video(segments.next()),
+                    Some(handle) if handle.starts_with('@') => Self::channel(handle, None),
+                    Some(prefix) if CHANNEL_PATHS.contains(&prefix) => {
+                        Self::channel(prefix, segments.next())
+                    }
+                    _ => Err(SourceError::MissingId),
+                }
+            }
+            _ => Err(SourceError::UnsupportedHost(host.to_string())),
+        }
+    }
+
+    fn video(id: Option<&str>) -> Result<Self, SourceError> {
+        match id {
+            Some(id) if id.len() == 11 && Self::valid_id(id) => Ok(Self::Video(id.to_string())),
+            Some("") | None => Err(SourceError::MissingId),
+            Some(id) => Err(SourceError::InvalidId(id.to_string())),
+        }
+    }
+
+    fn playlist(id: Option<&str>) -> Result<Self, SourceError> {
+        match id {
+            Some(id) if !id.is_empty() && Self::valid_id(id) => Ok(Self::Playlist(id.to_string())),
+            Some("") | None => Err(SourceError::MissingId),
+            Some(id) => Err(SourceError::InvalidId(id.to_string())),
+        }
+    }
+
+    fn channel(prefix: &str, name: Option<&str>) -> Result<Self, SourceError> {
+        let path = match name {
+            Some("") => return Err(SourceError::MissingId),
+            Some(name) => format!("{prefix}/{name}"),
+            None if prefix.len() > 1 => prefix.to_string(),
+            None => return Err(SourceError::MissingId),
+        };
+        let valid = path
+            .trim_start_matches('@')
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/'));
+        if valid {
+            Ok(Self::Channel(path))
+        } else {
+            Err(SourceError::InvalidId(path))
+        }
+    }
+
+    fn valid_id(id: &str) -> bool {
+        id.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    }
+
+    pub fn id(&self) -> &str {
+        match self {
+            Self::Video(id) | Self::Playlist(id) | Self::Channel(id) => id,
+        }
+    }
+
+    // the url that is handed to the extractor, list and timestamp parameters are dropped
+    pub fn canonical_url(&self) -> String {
+        match self {
+            Self::Video(id) => format!("https://www.youtube.com/watch?v={id}"),
+            Self::Playlist(id) => format!("https://www.youtube.com/playlist?list={id}"),
+            Self::Channel(path) => format!("https://www.youtube.com/{path}/videos"),
+        }
+    }
+
+    pub fn is_collection(&self) -> bool {
+        matches!(self, Self::Playlist(_) | Self::Channel(_))
+    }
+}
+
+// output of yt-dlp --flat-playlist --dump-single-json
+#[derive(Deserialize)]
+pub struct PlaylistInfo {
+    pub title: String,
+    pub entries: Vec<PlaylistEntry>,
+}
+
+#[derive(Deserialize)]
+pub struct PlaylistEntry {
+    pub id: String,
+}
+
+// a single progress line from yt-dlp, only lines that look like
+// [download]  42.3% of    3.45MiB at    1.23MiB/s ETA 00:02
+// are parsed, everything else is ignored
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Progress {
+    pub percent: f32,
+    // bytes per second
+    pub speed: Option<f64>,
+    // seconds left
+    pub eta: Option<u64>,
+}
+
+impl Progress {
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut tokens = line.split_whitespace();
+        if tokens.next()? != "[download]" {
+            return None;
+        }
+        let percent = tokens.next()?.strip_suffix('%')?.parse::<f32>().ok()?;
+        let mut speed = None;
+        let mut eta = None;
+        while let Some(token) = tokens.next() {
+            match token {
+                "at" => speed = tokens.next().and_then(parse_speed),
+                "ETA" => eta = tokens.next().and_then(parse_eta),
+                _ => (),
+            }
+        }
+        Some(Self {
+            percent,
+            speed,
+            eta,
+        })
+    }
+}
+
+fn parse_speed(speed: &str) -> Option<f64> {
+    let speed = speed.strip_suffix("/s")?;
+    let split = speed.find(|c: char| c.is_ascii_alphabetic())?;
+    let (value, unit) = speed.split_at(split);
+    let multiplier = match unit {
+        "B" => 1.0,
+        "KiB" => 1024.0,
+        "MiB" => 1024.0 * 1024.0,
+        "GiB" => 1024.0 * 1024.0 * 1024.0,
+        "KB" => 1000.0,
+        "MB" => 1000.0 * 1000.0,
+        "GB" => 1000.0 * 1000.0 * 1000.0,
+        _ => return None,
+    };
+    Some(value.parse::<f64>().ok()? * multiplier)
+}
+
+fn parse_eta(eta: &str) -> Option<u64> {
+    eta.split(':')
+        .try_fold(0, |acc, x| Some(acc * 60 + x.parse::<u64>().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sources() {
+        let video = |id: &str| Ok(Source::Video(id.to_string()));
+        let playlist = |id: &str| Ok(Source::Playlist(id.to_string()));
+        let channel = |path: &str| Ok(Source::Channel(path.to_string()));
+        let cases = [
+            ("https://www.youtube.com/watch?v=dQw4w9WgXcQ", video("dQw4w9WgXcQ")),
+            ("youtube.com/watch?v=dQw4w9WgXcQ", video("dQw4w9WgXcQ")),
+            ("  https://youtube.com/watch?v=dQw4w9WgXcQ  ", video("dQw4w9WgXcQ")),
+            ("https://youtu.be/dQw4w9WgXcQ", video("dQw4w9WgXcQ")),
+            ("https://youtu.be/dQw4w9WgXcQ?si=abc&t=42", video("dQw4w9WgXcQ")),
+            ("https://youtu.be/dQw4w9WgXcQ/", video("dQw4w9WgXcQ")),
+            ("https://www.youtube.com/shorts/dQw4w9WgXcQ", video("dQw4w9WgXcQ")),
+            ("https://youtube.com/shorts/dQw4w9WgXcQ/?feature=share", video("dQw4w9WgXcQ")),
+            ("https://www.youtube.com/embed/dQw4w9WgXcQ", video("dQw4w9WgXcQ")),
+            ("https://music.youtube.com/watch?v=dQw4w9WgXcQ", video("dQw4w9WgXcQ")),
+            ("https://m.youtube.com/watch?v=dQw4w9WgXcQ&t=10s", video("dQw4w9WgXcQ")),
+            (
+                "https://www.youtube.com/watch?t=5&v=dQw4w9WgXcQ&list=PLx0sYbCqOb8TBPRdmBHs5Iftvv9TPboYG",
+                video("dQw4w9WgXcQ"),
+            ),
+            (
+                "https://www.youtube.com/playlist?list=PLx0sYbCqOb8TBPRdmBHs5Iftvv9TPboYG",
+                playlist("PLx0sYbCqOb8TBPRdmBHs5Iftvv9TPboYG"),
+            ),
+            (
+                "https://music.youtube.com/playlist?list=OLAK5uy_abc-123&si=xyz",
+                playlist("OLAK5uy_abc-123"),
+            ),
+            ("https://www.youtube.com/@SomeHandle", channel("@SomeHandle")),
+            ("https://www.youtube.com/@some.handle/videos", channel("@some.handle")),
+            ("https://m.youtube.com/@SomeHandle/", channel("@SomeHandle")),
+            (
+                "https://www.youtube.com/channel/UC38IQsAvIsxxjztdMZQtwHA/",
+                channel("channel/UC38IQsAvIsxxjztdMZQtwHA"),
+            ),
+            ("https://www.youtube.com/c/SomeName", channel("c/SomeName")),
+            ("https://www.youtube.com/user/someone?sub=1", channel("user/someone")),
+            (
+                "https://vimeo.com/123456",
+                Err(SourceError::UnsupportedHost("vimeo.com".to_string())),
+            ),
+            (
+                "https://notyoutube.com/watch?v=dQw4w9WgXcQ",
+                Err(SourceError::UnsupportedHost("notyoutube.com".to_string())),
+            ),
+            ("ftp://youtube.com/watch?v=dQw4w9WgXcQ", Err(SourceError::InvalidUrl)),
+            ("https://", Err(SourceError::InvalidUrl)),
+            ("https://www.youtube.com/watch", Err(SourceError::MissingId)),
+            ("https://www.youtube.com/watch?v=", Err(SourceError::MissingId)),
+            ("https://youtu.be/", Err(SourceError::MissingId)),
+            ("https://www.youtube.com/feed/trending", Err(SourceError::MissingId)),
+            ("https://www.youtube.com/@", Err(SourceError::MissingId)),
+            (
+                "https://www.youtube.com/watch?v=short",
+                Err(SourceError::InvalidId("short".to_string())),
+            ),
+            (
+                "https://youtu.be/dQw4w9WgXcQQ",
+                Err(SourceError::InvalidId("dQw4w9WgXcQQ".to_string())),
+            ),
+            (
+                "https://www.youtube.com/watch?v=dQw4w9WgX%2FQ",
+                Err(SourceError::InvalidId("dQw4w9WgX/Q".to_string())),
+            ),
+            (
+                "https://www.youtube.com/playlist?list=PL%20bad",
+                Err(SourceError::InvalidId("PL bad".to_string())),
+            ),
+        ];
+        for (url, expected) in cases {
+            assert_eq!(Source::parse(url), expected, "{url}");
+        }
+    }
+
+    #[test]
+    fn canonical_urls() {
+        let source = Source::parse("https://youtu.be/dQw4w9WgXcQ?t=42").unwrap();
+        assert_eq!(
+            source.canonical_url(),
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ"
+        );
+        assert!(!source.is_collection());
+        let source = Source::parse("https://music.youtube.com/playlist?list=PLabc").unwrap();
+        assert_eq!(
+            source.canonical_url(),
+            "https://www.youtube.com/playlist?list=PLabc"
+        );
+        assert!(source.is_collection());
+        let source = Source::parse("https://www.youtube.com/@SomeHandle").unwrap();
+        assert_eq!(
+            source.canonical_url(),
+            "https://www.youtube.com/@SomeHandle/videos"
+        );
+    }
+
+    #[test]
+    fn progress_lines() {
+        let cases: [(&str, Option<Progress>); 8] = [
+            (
+                "[download]  42.3% of    3.45MiB at    1.50MiB/s ETA 00:02",
+                Some(Progress {
+                    percent: 42.3,
+                    speed: Some(1.5 * 1024.0 * 1024.0),
+                    eta: Some(2),
+                }),
+            ),
+            (
+                "[download]  12.0% of ~  3.21MiB at  200.00KiB/s ETA 01:02:03 (frag 3/10)",
+                Some(Progress {
+                    percent: 12.0,
+                    speed: Some(200.0 * 1024.0),
+                    eta: Some(3723),
+                }),
+            ),
+            (
+                "[download]   5.0% of ~3.21MiB at Unknown B/s ETA Unknown",
+                Some(Progress {
+                    percent: 5.0,
+                    speed: None,
+                    eta: None,
+                }),
+            ),
+            (
+                "[download] 100% of    3.45MiB in 00:00:03 at 1.00MB/s",
+                Some(Progress {
+                    percent: 100.0,
+                    speed: Some(1000.0 * 1000.0),
+                    eta: None,
+                }),
+            ),
+            ("[download] Downloading fragment 3 of 10", None),
+            ("[download] Destination: songs/abc.webm", None),
+            ("[ExtractAudio] Destination: songs/abc.mp3", None),
+            ("", None),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(Progress::parse(line), expected, "{line}");
+        }
+    }
+
+    #[test]
+    fn speeds() {
+        assert_eq!(parse_speed("10B/s"), Some(10.0));
+        assert_eq!(parse_speed("1.5KB/s"), Some(1500.0));
+        assert_eq!(parse_speed("2GiB/s"), Some(2.0 * 1024.0 * 1024.0 * 1024.0));
+        assert_eq!(parse_speed("Unknown"), None);
+        assert_eq!(parse_speed("1.5XB/s"), None);
+        assert_eq!(parse_speed("1.5MiB"), None);
+    }
+
+    #[test]
+    fn etas() {
+        assert_eq!(parse_eta("07"), Some(7));
+        assert_eq!(parse_eta("01:30"), Some(90));
+        assert_eq!(parse_eta("1:00:00"), Some(3600));
+        assert_eq!(parse_eta("Unknown"), None);
+        assert_eq!(parse_eta("00:xx"), None);
+    }
+}